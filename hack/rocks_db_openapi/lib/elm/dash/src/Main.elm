@@ -72,7 +72,7 @@ getObjects : Cmd Msg
 getObjects = 
     Http.get 
     { url = "/api/config/users"
-    , expect = Http.expectJson GotUsers userListDecoder
+    , expect = Http.expectJson GotUsers (Decode.oneOf [ userListDecoder, field "items" userListDecoder ])
     }
 
 update : Msg -> Model -> ( Model, Cmd Msg )
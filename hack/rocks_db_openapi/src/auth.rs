@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use poem::{
+    http::{header, StatusCode},
+    Endpoint, EndpointExt, IntoResponse, Request, Response,
+};
+
+/// Realm sent w/ basic auth challenges
+const REALM: &str = "rocks_db_openapi";
+
+/// Credentials required to call the api and open the swagger ui, if neither is set requests are not authenticated
+///
+/// Requests can authenticate w/ either `Authorization: Basic ..` or `Authorization: Bearer {token}`
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    /// Username and password for basic auth
+    pub basic: Option<(String, String)>,
+    /// Token for bearer auth
+    pub token: Option<String>,
+    /// Set when the credentials could not be used, ex. a username w/o a password, every request is rejected w/ 503
+    pub misconfigured: Option<String>,
+}
+
+impl Auth {
+    /// Returns auth w/ the credentials that are set, a username requires a password
+    pub fn new(
+        username: Option<String>,
+        password: Option<String>,
+        token: Option<String>,
+    ) -> Result<Self, String> {
+        let basic = match (username, password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => return Err("basic auth requires both a username and a password".to_string()),
+        };
+
+        Ok(Self {
+            basic,
+            token,
+            misconfigured: None,
+        })
+    }
+
+    /// Returns auth that rejects every request, for when the credentials that were set can't be used
+    ///
+    /// Used instead of falling back to fewer, or no, credentials
+    pub fn misconfigured(err: impl Into<String>) -> Self {
+        Self {
+            misconfigured: Some(err.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if credentials are required
+    pub fn is_enabled(&self) -> bool {
+        self.basic.is_some() || self.token.is_some() || self.misconfigured.is_some()
+    }
+
+    /// Wraps an endpoint so that requests w/o valid credentials are rejected w/ 401, or every request w/ 503 if auth is misconfigured
+    pub fn protect<E: Endpoint + 'static>(&self, endpoint: E) -> impl Endpoint {
+        let auth = Arc::new(self.clone());
+        endpoint.around(move |endpoint, request| {
+            let auth = auth.clone();
+            async move {
+                if let Some(err) = auth.misconfigured.as_ref() {
+                    return Ok(Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .body(format!("auth is misconfigured, {err}")));
+                }
+
+                if auth.authorize(&request) {
+                    Ok(endpoint.call(request).await?.into_response())
+                } else {
+                    Ok(auth.challenge())
+                }
+            }
+        })
+    }
+
+    fn authorize(&self, request: &Request) -> bool {
+        if self.misconfigured.is_some() {
+            return false;
+        }
+
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let authorization = match request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+        {
+            Some(authorization) => authorization,
+            None => return false,
+        };
+
+        match authorization.split_once(' ') {
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                let decoded = base64::decode(credentials.trim())
+                    .ok()
+                    .and_then(|d| String::from_utf8(d).ok());
+
+                match (self.basic.as_ref(), decoded.as_ref().and_then(|d| d.split_once(':'))) {
+                    (Some((username, password)), Some((u, p))) => {
+                        // evaluate both so the comparison time doesn't depend on which one is wrong
+                        let username_matches = constant_time_eq(username, u);
+                        let password_matches = constant_time_eq(password, p);
+                        username_matches && password_matches
+                    }
+                    _ => false,
+                }
+            }
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("bearer") => self
+                .token
+                .as_ref()
+                .is_some_and(|token| constant_time_eq(token, credentials.trim())),
+            _ => false,
+        }
+    }
+
+    fn challenge(&self) -> Response {
+        let challenge = if self.basic.is_some() {
+            format!("Basic realm=\"{REALM}\"")
+        } else {
+            format!("Bearer realm=\"{REALM}\"")
+        };
+
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, challenge)
+            .finish()
+    }
+}
+
+/// Compares two strings w/o returning early on the first difference
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,378 @@
+//! OpenAPI inspector for the connected registry rocksdb stores
+//!
+//! The inspector can be served on its own by the `rocks_db_openapi` binary, or mounted as a `WebApp`, ex. by chiron's `inspector` plugin
+use poem::{endpoint::StaticFilesEndpoint, Route};
+use poem_openapi::{
+    param::{Path, Query},
+    payload::Json,
+    OpenApi, OpenApiService,
+};
+use chrono::Duration;
+use std::collections::BTreeMap;
+
+use lifec::plugins::ThunkContext;
+use lifec_poem::WebApp;
+
+mod auth;
+pub use auth::Auth;
+
+pub mod models;
+use models::{
+    error::{DbResponse, TextResponse},
+    PageQuery,
+};
+use models::checks::{run_checks, Checks, DEFAULT_STALE_LOCK_HOURS};
+use models::browse::{get_raw_records, list_column_families, ColumnFamily, RawRecord};
+use models::config::{
+    get_registry_config_store_config, get_registry_config_store_users, get_registry_map_store,
+    Config, RegistryMapStore, User,
+};
+use models::topology::{get_registry_topology, Topology};
+use models::snapshot::{diff_snapshots, SnapshotDiff, SnapshotDiffRequest};
+use models::metadata::{
+    get_repository_detail, list_repositories, list_repository_manifests, list_repository_tags,
+    registry_store_path, ManifestMetadata, MetadataFilter, Repository, RepositoryDetail,
+    TagMetadata,
+};
+
+pub const CONFIG_STORE_ROOT_PATH: &str = "/var/acr/data/rocksdb/config";
+pub const METADATA_STORE_ROOT_PATH: &str = "/var/acr/data/rocksdb/metadata";
+pub const DASH_DIR: &str = "/root/dash";
+const DEFAULT_PAGE_LIMIT: usize = 100;
+const MAX_PAGE_LIMIT: usize = 1000;
+
+pub struct Api(
+    /// config store path
+    String,
+    /// metadata store path
+    String, 
+);
+
+#[OpenApi]
+impl Api {
+    #[oai(path = "/config", method = "get")]
+    async fn config(&self) -> DbResponse<Vec<Config>> {
+        let Self(config_store_path, _) = self;
+
+        get_registry_config_store_config(config_store_path).into()
+    }
+
+    #[oai(path = "/config/registrymap", method = "get")]
+    async fn config_registry_map(&self) -> DbResponse<Vec<RegistryMapStore>> {
+        let Self(config_store_path, _) = self;
+
+        get_registry_map_store(config_store_path).into()
+    }
+
+    /// Gets the connected registry hierarchy, w/ orphans, cycles and the registries below them, built from the registry map
+    #[oai(path = "/config/topology", method = "get")]
+    async fn config_topology(&self) -> DbResponse<Topology> {
+        let Self(config_store_path, _) = self;
+
+        get_registry_topology(config_store_path).into()
+    }
+
+    /// Renders the connected registry hierarchy as a graphviz digraph, ex. `curl /api/config/topology/dot | dot -Tsvg`
+    #[oai(path = "/config/topology/dot", method = "get")]
+    async fn config_topology_dot(&self) -> TextResponse {
+        let Self(config_store_path, _) = self;
+
+        get_registry_topology(config_store_path).map(|t| t.to_dot()).into()
+    }
+
+    #[oai(path = "/config/users", method = "get")]
+    async fn config_users(&self) -> DbResponse<Vec<BTreeMap<String, User>>> {
+        let Self(config_store_path, _) = self;
+
+        get_registry_config_store_users(config_store_path).into()
+    }
+
+    /// Lists the column families in the config store, w/ the number of keys in each
+    #[oai(path = "/config/column_families", method = "get")]
+    async fn config_column_families(&self) -> DbResponse<Vec<ColumnFamily>> {
+        let Self(config_store_path, _) = self;
+
+        list_column_families(config_store_path).into()
+    }
+
+    /// Lists raw records from a column family in the config store a page at a time
+    #[oai(path = "/config/column_families/:name", method = "get")]
+    async fn config_column_family(
+        &self,
+        name: Path<String>,
+        limit: Query<Option<u32>>,
+        cursor: Query<Option<String>>,
+        prefix: Query<Option<String>>,
+        start: Query<Option<String>>,
+        end: Query<Option<String>>,
+    ) -> DbResponse<Vec<RawRecord>> {
+        let Self(config_store_path, _) = self;
+
+        let query = page_query(limit, cursor, prefix, start, end);
+
+        get_raw_records(config_store_path, name.as_str(), &query).into()
+    }
+
+    /// Lists the column families in a registry's metadata store, w/ the number of keys in each
+    #[oai(path = "/metadata/column_families", method = "get")]
+    async fn metadata_column_families(&self, registry_id: Query<String>) -> DbResponse<Vec<ColumnFamily>> {
+        let Self(_, metadata_store_path) = self;
+
+        registry_store_path(metadata_store_path, registry_id.as_str())
+            .and_then(|path| list_column_families(&path))
+            .into()
+    }
+
+    /// Lists raw records from a column family in a registry's metadata store a page at a time
+    #[oai(path = "/metadata/column_families/:name", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn metadata_column_family(
+        &self,
+        name: Path<String>,
+        registry_id: Query<String>,
+        limit: Query<Option<u32>>,
+        cursor: Query<Option<String>>,
+        prefix: Query<Option<String>>,
+        start: Query<Option<String>>,
+        end: Query<Option<String>>,
+    ) -> DbResponse<Vec<RawRecord>> {
+        let Self(_, metadata_store_path) = self;
+
+        let query = page_query(limit, cursor, prefix, start, end);
+
+        registry_store_path(metadata_store_path, registry_id.as_str())
+            .and_then(|path| get_raw_records(&path, name.as_str(), &query))
+            .into()
+    }
+
+    /// Cross-validates the config store, and a registry's metadata store if `registry_id` is set, reporting findings most severe first
+    ///
+    /// Repository locks older than `stale_lock_hours`, 24 by default, are reported as stale
+    #[oai(path = "/checks", method = "get")]
+    async fn checks(
+        &self,
+        registry_id: Query<Option<String>>,
+        stale_lock_hours: Query<Option<i64>>,
+    ) -> DbResponse<Checks> {
+        let Self(config_store_path, metadata_store_path) = self;
+
+        let metadata_store = match registry_id.0 {
+            Some(registry_id) => match registry_store_path(metadata_store_path, &registry_id) {
+                Ok(path) => Some(path),
+                Err(err) => return err.into(),
+            },
+            None => None,
+        };
+        let stale_lock = Duration::hours(stale_lock_hours.0.unwrap_or(DEFAULT_STALE_LOCK_HOURS));
+
+        run_checks(config_store_path, metadata_store.as_deref(), stale_lock).into()
+    }
+
+    /// Diffs two json-lines snapshots, exported w/ the snapshot command
+    #[oai(path = "/snapshots/diff", method = "post")]
+    async fn snapshots_diff(&self, request: Json<SnapshotDiffRequest>) -> DbResponse<SnapshotDiff> {
+        let SnapshotDiffRequest { before, after } = request.0;
+
+        diff_snapshots(&before, &after).into()
+    }
+
+    /// Lists repositories matching the filters a page at a time, pass the `X-Next-Cursor` header back as `cursor` to read the next page
+    #[oai(path = "/metadata/repositories", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn metadata_repositories(
+        &self,
+        registry_id: Query<String>,
+        limit: Query<Option<u32>>,
+        cursor: Query<Option<String>>,
+        prefix: Query<Option<String>>,
+        start: Query<Option<String>>,
+        end: Query<Option<String>>,
+        repository: Query<Option<String>>,
+        updated_after: Query<Option<String>>,
+        updated_before: Query<Option<String>>,
+        created_after: Query<Option<String>>,
+        created_before: Query<Option<String>>,
+    ) -> DbResponse<Vec<Repository>> {
+        let Self(_, metadata_store_path) = self;
+
+        let query = page_query(limit, cursor, prefix, start, end);
+        let filter = MetadataFilter {
+            repository: repository.0,
+            updated_after: updated_after.0,
+            updated_before: updated_before.0,
+            created_after: created_after.0,
+            created_before: created_before.0,
+            ..Default::default()
+        };
+
+        list_repositories(metadata_store_path, registry_id.as_str(), &query, &filter).into()
+    }
+
+    /// Gets a repository by name, w/ all of its tags and manifests
+    #[oai(path = "/metadata/repositories/:name", method = "get")]
+    async fn metadata_repository(
+        &self,
+        name: Path<String>,
+        registry_id: Query<String>,
+    ) -> DbResponse<RepositoryDetail> {
+        let Self(_, metadata_store_path) = self;
+
+        get_repository_detail(metadata_store_path, registry_id.as_str(), name.as_str()).into()
+    }
+
+    /// Lists manifest metadata matching the filters a page at a time, pass the `X-Next-Cursor` header back as `cursor` to read the next page
+    #[oai(path = "/metadata/manifests", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn metadata_manifests(
+        &self,
+        registry_id: Query<String>,
+        limit: Query<Option<u32>>,
+        cursor: Query<Option<String>>,
+        prefix: Query<Option<String>>,
+        start: Query<Option<String>>,
+        end: Query<Option<String>>,
+        repository: Query<Option<String>>,
+        digest: Query<Option<String>>,
+        updated_after: Query<Option<String>>,
+        updated_before: Query<Option<String>>,
+        created_after: Query<Option<String>>,
+        created_before: Query<Option<String>>,
+    ) -> DbResponse<Vec<ManifestMetadata>> {
+        let Self(_, metadata_store_path) = self;
+
+        let query = page_query(limit, cursor, prefix, start, end);
+        let filter = MetadataFilter {
+            repository: repository.0,
+            digest: digest.0,
+            updated_after: updated_after.0,
+            updated_before: updated_before.0,
+            created_after: created_after.0,
+            created_before: created_before.0,
+            ..Default::default()
+        };
+
+        list_repository_manifests(metadata_store_path, registry_id.as_str(), &query, &filter).into()
+    }
+
+    /// Lists tag metadata matching the filters a page at a time, pass the `X-Next-Cursor` header back as `cursor` to read the next page
+    #[oai(path = "/metadata/tags", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn metadata_tags(
+        &self,
+        registry_id: Query<String>,
+        limit: Query<Option<u32>>,
+        cursor: Query<Option<String>>,
+        prefix: Query<Option<String>>,
+        start: Query<Option<String>>,
+        end: Query<Option<String>>,
+        repository: Query<Option<String>>,
+        tag: Query<Option<String>>,
+        digest: Query<Option<String>>,
+        updated_after: Query<Option<String>>,
+        updated_before: Query<Option<String>>,
+        created_after: Query<Option<String>>,
+        created_before: Query<Option<String>>,
+    ) -> DbResponse<Vec<TagMetadata>> {
+        let Self(_, metadata_store_path) = self;
+
+        let query = page_query(limit, cursor, prefix, start, end);
+        let filter = MetadataFilter {
+            repository: repository.0,
+            tag: tag.0,
+            digest: digest.0,
+            updated_after: updated_after.0,
+            updated_before: updated_before.0,
+            created_after: created_after.0,
+            created_before: created_before.0,
+        };
+
+        list_repository_tags(metadata_store_path, registry_id.as_str(), &query, &filter).into()
+    }
+}
+
+/// Returns the page query for a paged endpoint's query parameters
+fn page_query(
+    Query(limit): Query<Option<u32>>,
+    Query(cursor): Query<Option<String>>,
+    Query(prefix): Query<Option<String>>,
+    Query(start): Query<Option<String>>,
+    Query(end): Query<Option<String>>,
+) -> PageQuery {
+    PageQuery {
+        limit: Some(limit.map_or(DEFAULT_PAGE_LIMIT, |l| l as usize).min(MAX_PAGE_LIMIT)),
+        cursor,
+        prefix,
+        start,
+        end,
+    }
+}
+
+/// Hosts the api at `/api`, the swagger ui at `/swagger`, and the dashboard at `/`
+///
+pub struct Inspector {
+    /// Path to the config store
+    pub config_store: String,
+    /// Path to the metadata store, each registry's store is at {metadata_store}/{registry_id}
+    pub metadata_store: String,
+    /// Directory the dashboard is served from
+    pub dash_dir: String,
+    /// Credentials required for the api and swagger ui
+    pub auth: Auth,
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self {
+            config_store: CONFIG_STORE_ROOT_PATH.to_string(),
+            metadata_store: METADATA_STORE_ROOT_PATH.to_string(),
+            dash_dir: DASH_DIR.to_string(),
+            auth: Auth::default(),
+        }
+    }
+}
+
+impl WebApp for Inspector {
+    /// Reads `config_store`, `metadata_store` and `dash_dir` from the context, using the defaults for any that aren't set
+    ///
+    /// If `auth_username` and `auth_password`, or `auth_token` are set, requests to the api and swagger ui must authenticate.
+    /// If only one of `auth_username` and `auth_password` is set, every request to the api and swagger ui is rejected w/ 503
+    fn create(context: &mut ThunkContext) -> Self {
+        let Self {
+            config_store,
+            metadata_store,
+            dash_dir,
+            ..
+        } = Self::default();
+
+        let graph = context.as_ref();
+        let auth = Auth::new(
+            graph.find_text("auth_username"),
+            graph.find_text("auth_password"),
+            graph.find_text("auth_token"),
+        )
+        .unwrap_or_else(Auth::misconfigured);
+
+        Self {
+            config_store: graph.find_text("config_store").unwrap_or(config_store),
+            metadata_store: graph.find_text("metadata_store").unwrap_or(metadata_store),
+            dash_dir: graph.find_text("dash_dir").unwrap_or(dash_dir),
+            auth,
+        }
+    }
+
+    fn routes(&mut self) -> Route {
+        let api = Api(self.config_store.to_string(), self.metadata_store.to_string());
+        let api_service = OpenApiService::new(api, "OnPrem Connected Registry", "0.1")
+            .server("/api");
+
+        let swagger = api_service.swagger_ui();
+
+        Route::new()
+            .nest("/api", self.auth.protect(api_service))
+            .nest("/swagger", self.auth.protect(swagger))
+            .at(
+                "/",
+                StaticFilesEndpoint::new(&self.dash_dir).index_file("index.html"),
+            )
+    }
+}
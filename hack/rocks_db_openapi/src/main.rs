@@ -1,151 +1,180 @@
+use clap::{Parser, Subcommand};
 use poem::{
-    endpoint::StaticFilesEndpoint,
-    http::StatusCode,
-    listener::TcpListener,
-    Route, Server,
+    listener::{Listener, RustlsCertificate, RustlsConfig, TcpListener},
+    Server,
 };
-use poem_openapi::{
-    param::Query,
-    payload::{Json, Response},
-    OpenApi, OpenApiService,
-};
-use std::collections::BTreeMap;
 
-use lifec::plugins::ThunkContext;
 use lifec_poem::WebApp;
-
-mod models;
-use models::config::{
-    get_registry_config_store_config, get_registry_config_store_users, get_registry_map_store,
-    Config, RegistryMapStore, User,
+use rocks_db_openapi::{
+    models::snapshot::{diff_snapshots, export_snapshot},
+    Auth, Inspector, CONFIG_STORE_ROOT_PATH, DASH_DIR, METADATA_STORE_ROOT_PATH,
 };
 
-use models::metadata::{
-    list_repositories, list_repository_manifests, list_repository_tags, ManifestMetadata,
-    Repository, TagMetadata,
-};
-
-const CONFIG_STORE_ROOT_PATH: &str = "/var/acr/data/rocksdb/config";
-const METADATA_STORE_ROOT_PATH: &str = "/var/acr/data/rocksdb/metadata";
-
-struct Api(
-    /// config store path
-    String,
-    /// metadata store path
-    String, 
-);
-
-#[OpenApi]
-impl Api {
-    #[oai(path = "/config", method = "get")]
-    async fn config(&self) -> Response<Json<Vec<Config>>> {
-        let Self(config_store_path, _) = self;
+const BIND_ADDRESS: &str = "0.0.0.0:8000";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rocks_db_openapi")]
+#[clap(about = "Serves an OpenAPI inspector for the connected registry rocksdb stores", long_about = None)]
+struct Cli {
+    /// Path to the config store
+    #[clap(long, env = "ROCKS_DB_CONFIG_STORE", default_value = CONFIG_STORE_ROOT_PATH)]
+    config_store: String,
+    /// Path to the metadata store, each registry's store is at {metadata_store}/{registry_id}
+    #[clap(long, env = "ROCKS_DB_METADATA_STORE", default_value = METADATA_STORE_ROOT_PATH)]
+    metadata_store: String,
+    /// Directory the dashboard is served from
+    #[clap(long, env = "ROCKS_DB_DASH_DIR", default_value = DASH_DIR)]
+    dash_dir: String,
+    /// Address to listen on
+    #[clap(long, env = "ROCKS_DB_BIND_ADDRESS", default_value = BIND_ADDRESS)]
+    address: String,
+    /// Path to a pem certificate, serves https w/ --tls-key
+    #[clap(long, env = "ROCKS_DB_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<String>,
+    /// Path to the pem private key of --tls-cert
+    #[clap(long, env = "ROCKS_DB_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<String>,
+    /// Serves https w/ a self-signed certificate for localhost, for local use only
+    #[clap(long, env = "ROCKS_DB_SELF_SIGNED", conflicts_with = "tls_cert")]
+    self_signed: bool,
+    /// Username required for the api and swagger ui, w/ --password
+    #[clap(long, env = "ROCKS_DB_USERNAME")]
+    username: Option<String>,
+    /// Password required for the api and swagger ui, w/ --username
+    #[clap(long, env = "ROCKS_DB_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    /// Bearer token accepted for the api and swagger ui
+    #[clap(long, env = "ROCKS_DB_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Serves the inspector if not set
+    #[clap(subcommand)]
+    command: Option<Commands>,
+}
 
-        match get_registry_config_store_config(config_store_path) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
-        }
-    }
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Exports every column family of a store to a json-lines snapshot
+    Snapshot {
+        /// Path to the store, ex. the config store, or {metadata_store}/{registry_id}
+        store: String,
+        /// File to write the snapshot to, stdout if not set
+        #[clap(long, short)]
+        output: Option<String>,
+    },
+    /// Diffs two json-lines snapshots, printing added, removed and changed keys as json
+    Diff {
+        /// Snapshot from before
+        before: String,
+        /// Snapshot from after
+        after: String,
+    },
+}
 
-    #[oai(path = "/config/registrymap", method = "get")]
-    async fn config_registry_map(&self) -> Response<Json<Vec<RegistryMapStore>>> {
-        match get_registry_map_store(CONFIG_STORE_ROOT_PATH) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
+#[tokio::main]
+async fn main() -> Result<(), std::io::Error> {
+    let Cli {
+        config_store,
+        metadata_store,
+        dash_dir,
+        address,
+        tls_cert,
+        tls_key,
+        self_signed,
+        username,
+        password,
+        token,
+        command,
+    } = Cli::parse();
+
+    match command {
+        Some(Commands::Snapshot { store, output }) => {
+            let result = match output.as_ref() {
+                Some(output) => std::fs::File::create(output)
+                    .map_err(|e| format!("could not create {output}, {e}"))
+                    .and_then(|file| export_snapshot(&store, file).map_err(|e| e.to_string())),
+                None => export_snapshot(&store, std::io::stdout().lock()).map_err(|e| e.to_string()),
+            };
+
+            match result {
+                Ok(count) => eprintln!("exported {count} record(s) from {store}"),
+                Err(err) => {
+                    eprintln!("could not export snapshot, {err}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
         }
-    }
-    #[oai(path = "/config/users", method = "get")]
-    async fn config_users(&self) -> Response<Json<Vec<BTreeMap<String, User>>>> {
-        match get_registry_config_store_users(CONFIG_STORE_ROOT_PATH) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
+        Some(Commands::Diff { before, after }) => {
+            let diff = std::fs::read_to_string(&before)
+                .and_then(|b| std::fs::read_to_string(&after).map(|a| (b, a)))
+                .map_err(|e| e.to_string())
+                .and_then(|(b, a)| diff_snapshots(&b, &a).map_err(|e| e.to_string()))
+                .and_then(|diff| serde_json::to_string_pretty(&diff).map_err(|e| e.to_string()));
+
+            match diff {
+                Ok(diff) => println!("{diff}"),
+                Err(err) => {
+                    eprintln!("could not diff snapshots, {err}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
         }
+        None => {}
     }
 
-    #[oai(path = "/metadata/repositories", method = "get")]
-    async fn metadata_repositories(
-        &self,
-        registry_id: Query<String>,
-    ) -> Response<Json<Vec<Repository>>> {
-        match list_repositories(METADATA_STORE_ROOT_PATH, registry_id.as_str()) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
+    let auth = match Auth::new(username, password, token) {
+        Ok(auth) => auth,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
         }
-    }
+    };
 
-    #[oai(path = "/metadata/manifests", method = "get")]
-    async fn metadata_manifests(
-        &self,
-        registry_id: Query<String>,
-    ) -> Response<Json<Vec<ManifestMetadata>>> {
-        match list_repository_manifests(METADATA_STORE_ROOT_PATH, registry_id.as_str()) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
-        }
+    if !auth.is_enabled() {
+        eprintln!("warning: no credentials set, the api and swagger ui are not authenticated");
     }
 
-    #[oai(path = "/metadata/tags", method = "get")]
-    async fn metadata_tags(&self, registry_id: Query<String>) -> Response<Json<Vec<TagMetadata>>> {
-        match list_repository_tags(METADATA_STORE_ROOT_PATH, registry_id.as_str()) {
-            Some(map) => Response::new(Json(map)),
-            _ => Response::new(Json(vec![])).status(StatusCode::NOT_FOUND),
-        }
+    let app = Inspector {
+        config_store,
+        metadata_store,
+        dash_dir,
+        auth,
     }
-}
-
-impl WebApp for Api {
-    fn create(_: &mut ThunkContext) -> Self {
-        todo!()
-    }
-
-    fn routes(&mut self) -> Route {
-        let api = Api(CONFIG_STORE_ROOT_PATH.to_string(), METADATA_STORE_ROOT_PATH.to_string());
-        let api_service = OpenApiService::new(api, "OnPrem Connected Registry", "0.1")
-            .server("http://localhost:8000/api");
-
-        let swagger = api_service.swagger_ui();
-
-        Route::new()
-            .nest("/api", api_service)
-            .nest("/swagger", swagger)
-            .at(
-                "/",
-                StaticFilesEndpoint::new("/root/dash").index_file("index.html"),
-            )
+    .routes();
+
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            let cert = std::fs::read_to_string(&cert)
+                .map_err(|e| format!("could not read {cert}, {e}"));
+            let key = std::fs::read_to_string(&key)
+                .map_err(|e| format!("could not read {key}, {e}"));
+            Some(cert.and_then(|cert| key.map(|key| (cert, key))))
+        }
+        _ if self_signed => Some(
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                .and_then(|c| Ok((c.serialize_pem()?, c.serialize_private_key_pem())))
+                .map_err(|e| format!("could not generate a self-signed certificate, {e}")),
+        ),
+        _ => None,
+    };
+
+    match tls {
+        Some(Ok((cert, key))) => {
+            let config = RustlsConfig::new().fallback(RustlsCertificate::new().key(key).cert(cert));
+
+            Server::new(TcpListener::bind(address).rustls(config))
+                .run(app)
+                .await
+        }
+        Some(Err(err)) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        None => {
+            Server::new(TcpListener::bind(address))
+                .run(app)
+                .await
+        }
     }
 }
-
-#[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let api_service = OpenApiService::new(
-            Api(
-                CONFIG_STORE_ROOT_PATH.to_string(), 
-                METADATA_STORE_ROOT_PATH.to_string()
-            ), 
-    "OnPrem Connected Registry", 
-        "0.1"
-        ).server("http://localhost:8000/api");
-
-    let swagger = api_service.swagger_ui();
-
-    let app = Route::new()
-        .nest("/api", api_service)
-        .nest("/swagger", swagger)
-        .at(
-            "/",
-            StaticFilesEndpoint::new("/root/dash").index_file("index.html"),
-        );
-
-    // Enable TLS
-    // let key = fs::read_to_string("/root/certs/tls.key").unwrap();
-    // let cert = fs::read_to_string("/root/certs/tls.crt").unwrap();
-
-    // Server::new(TcpListener::bind("0.0.0.0:8443")
-    //     .rustls(RustlsConfig::new().key(key).cert(cert)))
-    //     .run(app)
-    //     .await
-
-    Server::new(TcpListener::bind("0.0.0.0:8000"))
-        .run(app)
-        .await
-}
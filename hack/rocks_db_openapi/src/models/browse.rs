@@ -0,0 +1,104 @@
+use poem_openapi::Object;
+use rocksdb::IteratorMode;
+use serde::Serialize;
+
+use crate::models::{
+    config::{redact_passwords, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, REDACTED},
+    error::StoreError,
+    open_db, scan, to_hex, Page, PageQuery,
+};
+
+/// Lists the column families in the store at `path`, w/ the number of keys in each
+///
+/// Keys are counted by iterating each column family, so this reads the entire store
+pub fn list_column_families(path: &str) -> Result<Vec<ColumnFamily>, StoreError> {
+    let (opened, cfs) = open_db(path)?;
+
+    let mut column_families = vec![];
+    for name in cfs {
+        let key_count = match opened.cf_handle(&name) {
+            Some(handle) => opened.iterator_cf(handle, IteratorMode::Start).count() as u64,
+            None => continue,
+        };
+
+        column_families.push(ColumnFamily { name, key_count });
+    }
+
+    Ok(column_families)
+}
+
+/// Get a page of raw records from the column family: `object_name`, without deserializing them to a typed model
+pub fn get_raw_records(path: &str, object_name: &str, query: &PageQuery) -> Result<Page<RawRecord>, StoreError> {
+    let mut items = vec![];
+    let next_cursor = scan(path, object_name, query, |k, v| {
+        items.push(RawRecord::new(object_name, k, v));
+        true
+    })?;
+
+    Ok(Page { items, next_cursor, errors: vec![] })
+}
+
+#[derive(Object, Serialize, Debug)]
+pub struct ColumnFamily {
+    name: String,
+    key_count: u64,
+}
+
+/// A record as it is stored
+#[derive(Object, Serialize, Debug)]
+pub struct RawRecord {
+    /// The key, lossy utf8
+    key: String,
+    /// The key as hex
+    key_hex: String,
+    /// `json` if the value parsed as json, `redacted` for a users record that didn't, otherwise `hex`
+    format: String,
+    /// The value, pretty-printed json w/ password hashes redacted, `<redacted>`, or hex
+    value: String,
+}
+
+impl RawRecord {
+    /// Users records that aren't json are never returned, since their password hash can't be redacted
+    fn new(object_name: &str, key: &[u8], value: &[u8]) -> Self {
+        let (format, value) = match serde_json::from_slice::<serde_json::Value>(value)
+            .ok()
+            .and_then(|mut v| {
+                redact_passwords(&mut v);
+                serde_json::to_string_pretty(&v).ok()
+            })
+        {
+            Some(pretty) => ("json", pretty),
+            None if object_name == COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS => ("redacted", REDACTED.to_string()),
+            None => ("hex", to_hex(value)),
+        };
+
+        RawRecord {
+            key: String::from_utf8_lossy(key).to_string(),
+            key_hex: to_hex(key),
+            format: format.to_string(),
+            value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawRecord;
+    use crate::models::config::{COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS};
+
+    #[test]
+    fn test_raw_users_record() {
+        let record = RawRecord::new(COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, b"users", br#"{"u1":{"pwdJson":"s3cr3t-hash"}}"#);
+        assert_eq!(record.format, "json");
+        assert!(!record.value.contains("s3cr3t-hash"));
+
+        // Not json, so the hash can't be redacted, and the record is not returned at all
+        let value = br#"{"u1":{"pwdJson":"s3cr3t-hash""#;
+        let record = RawRecord::new(COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, b"users", value);
+        assert_eq!(record.format, "redacted");
+        assert_eq!(record.value, "<redacted>");
+
+        let record = RawRecord::new(COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG, b"config", value);
+        assert_eq!(record.format, "hex");
+    }
+}
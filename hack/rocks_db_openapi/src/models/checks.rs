@@ -0,0 +1,335 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+    config::{
+        Config, RegistryMapStore, COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG,
+        COLUMN_NAME_REGISTRY_MAP_STORE,
+    },
+    error::StoreError,
+    get_db_objects,
+    metadata::{
+        ManifestMetadata, Repository, TagMetadata, COLUMN_NAME_REPOSITORY,
+        COLUMN_NAME_REPOSITORY_MANIFEST_METADATA, COLUMN_NAME_REPOSITORY_TAG_METADATA,
+    },
+};
+
+/// Default age after which a repository lock is reported as stale
+pub const DEFAULT_STALE_LOCK_HOURS: i64 = 24;
+
+/// Severity of a finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Something a check found
+#[derive(Object, Serialize, Debug)]
+pub struct Finding {
+    /// `error`, `warning`, or `info`
+    pub severity: String,
+    /// Name of the check, ex. `tag_manifest_missing`
+    pub check: String,
+    /// What the finding is about, ex. `{repository_id}:{tag}`
+    pub subject: String,
+    pub message: String,
+}
+
+/// Findings from every check, most severe first
+#[derive(Object, Serialize, Debug, Default)]
+pub struct Checks {
+    pub errors: u32,
+    pub warnings: u32,
+    pub findings: Vec<Finding>,
+}
+
+/// Cross-validates the config store, and a registry's metadata store if `metadata_store` is set
+///
+/// Column families that are missing, or have records that can't be read, are reported as findings, and the checks that need them are skipped
+pub fn run_checks(
+    config_store: &str,
+    metadata_store: Option<&str>,
+    stale_lock: Duration,
+) -> Result<Checks, StoreError> {
+    let mut findings = vec![];
+
+    let configs = load::<Config>(config_store, COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG, &mut findings)?;
+    let registry_map = load::<RegistryMapStore>(config_store, COLUMN_NAME_REGISTRY_MAP_STORE, &mut findings)?;
+    if let (Some(configs), Some(registry_map)) = (configs, registry_map) {
+        check_registry_map(&configs, &registry_map, &mut findings);
+    }
+
+    if let Some(metadata_store) = metadata_store {
+        let repositories = load::<Repository>(metadata_store, COLUMN_NAME_REPOSITORY, &mut findings)?;
+        let tags = load::<TagMetadata>(metadata_store, COLUMN_NAME_REPOSITORY_TAG_METADATA, &mut findings)?;
+        let manifests =
+            load::<ManifestMetadata>(metadata_store, COLUMN_NAME_REPOSITORY_MANIFEST_METADATA, &mut findings)?;
+
+        if let (Some(tags), Some(manifests)) = (tags.as_ref(), manifests) {
+            check_tag_manifests(tags, &manifests, &mut findings);
+        }
+
+        if let Some(repositories) = repositories {
+            if let Some(tags) = tags.as_ref() {
+                check_untagged_repositories(&repositories, tags, &mut findings);
+            }
+            check_locks(&repositories, Utc::now(), stale_lock, &mut findings);
+        }
+    }
+
+    findings.sort_by(|(a, a_finding), (b, b_finding)| {
+        b.cmp(a)
+            .then_with(|| a_finding.check.cmp(&b_finding.check))
+            .then_with(|| a_finding.subject.cmp(&b_finding.subject))
+    });
+
+    let count = |severity| findings.iter().filter(|(s, _)| *s == severity).count() as u32;
+    Ok(Checks {
+        errors: count(Severity::Error),
+        warnings: count(Severity::Warning),
+        findings: findings.into_iter().map(|(_, f)| f).collect(),
+    })
+}
+
+/// Configs whose connected registry is missing from the registry map
+fn check_registry_map(configs: &[Config], registry_map: &[RegistryMapStore], findings: &mut Vec<(Severity, Finding)>) {
+    let registries = registry_map
+        .iter()
+        .map(|r| r.connected_registry_id())
+        .collect::<BTreeSet<_>>();
+
+    for config in configs {
+        if !registries.contains(config.connected_registry_id.as_str()) {
+            finding(
+                findings,
+                Severity::Error,
+                "config_registry_missing",
+                &config.connected_registry_id,
+                "config's connRegId is missing from RegistryMapStore",
+            );
+        }
+    }
+}
+
+/// Tags that point to a manifest that doesn't exist in the tag's repository
+fn check_tag_manifests(tags: &[TagMetadata], manifests: &[ManifestMetadata], findings: &mut Vec<(Severity, Finding)>) {
+    let manifests = manifests
+        .iter()
+        .map(|m| (m.repository_id.as_str(), m.digest.as_str()))
+        .collect::<BTreeSet<_>>();
+
+    for tag in tags {
+        if !manifests.contains(&(tag.repository_id.as_str(), tag.digest.as_str())) {
+            finding(
+                findings,
+                Severity::Error,
+                "tag_manifest_missing",
+                &format!("{}:{}", tag.repository_id, tag.tag),
+                &format!("tag points to {}, which is not in the repository's manifests", tag.digest),
+            );
+        }
+    }
+}
+
+/// Repositories w/o any tags
+fn check_untagged_repositories(repositories: &[Repository], tags: &[TagMetadata], findings: &mut Vec<(Severity, Finding)>) {
+    let tagged = tags.iter().map(|t| t.repository_id.as_str()).collect::<BTreeSet<_>>();
+
+    for repository in repositories {
+        if !tagged.contains(repository.repository_id.as_str()) {
+            finding(
+                findings,
+                Severity::Warning,
+                "repository_untagged",
+                &repository.repository_name,
+                "repository has no tags",
+            );
+        }
+    }
+}
+
+/// Locked repositories whose lock is older than `stale_lock`
+fn check_locks(repositories: &[Repository], now: DateTime<Utc>, stale_lock: Duration, findings: &mut Vec<(Severity, Finding)>) {
+    for repository in repositories.iter().filter(|r| r.locked) {
+        match DateTime::parse_from_rfc3339(&repository.locked_timestamp) {
+            Ok(locked) if now.signed_duration_since(locked) > stale_lock => finding(
+                findings,
+                Severity::Warning,
+                "repository_lock_stale",
+                &repository.repository_name,
+                &format!(
+                    "repository has been locked since {}, over {} hour(s) ago",
+                    repository.locked_timestamp,
+                    stale_lock.num_hours()
+                ),
+            ),
+            Ok(_) => continue,
+            Err(err) => finding(
+                findings,
+                Severity::Warning,
+                "repository_lock_unreadable",
+                &repository.repository_name,
+                &format!("repository is locked, but LockedTimestamp {:?} could not be parsed, {err}", repository.locked_timestamp),
+            ),
+        }
+    }
+}
+
+/// Loads every record in a column family, reporting the column family as a finding if it can't be read
+///
+/// Errors opening the store are returned
+fn load<O>(path: &str, column_family: &str, findings: &mut Vec<(Severity, Finding)>) -> Result<Option<Vec<O>>, StoreError>
+where
+    for<'a> O: Deserialize<'a> {
+    match get_db_objects::<O>(path, column_family).and_then(|page| page.or_invalid(column_family)) {
+        Ok(page) => Ok(Some(page.items)),
+        Err(StoreError::ColumnFamilyNotFound { .. }) => {
+            finding(findings, Severity::Info, "column_family_missing", column_family, "column family is not in the store, checks that need it were skipped");
+            Ok(None)
+        }
+        Err(StoreError::InvalidRecords { errors, .. }) => {
+            for error in errors {
+                finding(
+                    findings,
+                    Severity::Error,
+                    "record_unreadable",
+                    &format!("{column_family}/{}", error.key),
+                    &format!("{}, checks that need {column_family} were skipped", error.message),
+                );
+            }
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn finding(findings: &mut Vec<(Severity, Finding)>, severity: Severity, check: &str, subject: &str, message: &str) {
+    findings.push((
+        severity,
+        Finding {
+            severity: severity.as_str().to_string(),
+            check: check.to_string(),
+            subject: subject.to_string(),
+            message: message.to_string(),
+        },
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::{check_locks, check_tag_manifests, check_untagged_repositories, Severity};
+    use crate::models::metadata::{ManifestMetadata, Repository, TagMetadata};
+
+    fn repository(id: &str, locked_timestamp: Option<&str>) -> Repository {
+        let mut repository = Repository::default();
+        repository.repository_id = id.to_string();
+        repository.repository_name = format!("{id}-name");
+        if let Some(locked_timestamp) = locked_timestamp {
+            repository.locked = true;
+            repository.locked_timestamp = locked_timestamp.to_string();
+        }
+        repository
+    }
+
+    fn tag(repository_id: &str, tag: &str, digest: &str) -> TagMetadata {
+        let mut metadata = TagMetadata::default();
+        metadata.repository_id = repository_id.to_string();
+        metadata.tag = tag.to_string();
+        metadata.digest = digest.to_string();
+        metadata
+    }
+
+    fn manifest(repository_id: &str, digest: &str) -> ManifestMetadata {
+        let mut metadata = ManifestMetadata::default();
+        metadata.repository_id = repository_id.to_string();
+        metadata.digest = digest.to_string();
+        metadata
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2022-08-01T12:00:00Z")
+            .expect("should parse")
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_stale_lock() {
+        let repositories = [
+            repository("stale", Some("2022-07-30T12:00:00Z")),
+            repository("recent", Some("2022-08-01T11:00:00Z")),
+            repository("unreadable", Some("yesterday")),
+            repository("unlocked", None),
+        ];
+
+        let mut findings = vec![];
+        check_locks(&repositories, now(), Duration::hours(24), &mut findings);
+
+        let findings = findings
+            .iter()
+            .map(|(severity, f)| (*severity, f.check.as_str(), f.subject.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            vec![
+                (Severity::Warning, "repository_lock_stale", "stale-name"),
+                (Severity::Warning, "repository_lock_unreadable", "unreadable-name"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tag_manifest_missing() {
+        let tags = [
+            tag("r1", "v1", "sha256:a"),
+            tag("r1", "v2", "sha256:missing"),
+            // the digest exists, but in another repository
+            tag("r2", "v1", "sha256:a"),
+        ];
+        let manifests = [manifest("r1", "sha256:a")];
+
+        let mut findings = vec![];
+        check_tag_manifests(&tags, &manifests, &mut findings);
+
+        let findings = findings
+            .iter()
+            .map(|(severity, f)| (*severity, f.check.as_str(), f.subject.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            findings,
+            vec![
+                (Severity::Error, "tag_manifest_missing", "r1:v2"),
+                (Severity::Error, "tag_manifest_missing", "r2:v1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untagged_repository() {
+        let repositories = [repository("tagged", None), repository("untagged", None)];
+        let tags = [tag("tagged", "v1", "sha256:a")];
+
+        let mut findings = vec![];
+        check_untagged_repositories(&repositories, &tags, &mut findings);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].1.check, "repository_untagged");
+        assert_eq!(findings[0].1.subject, "untagged-name");
+    }
+}
@@ -1,29 +1,129 @@
 use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
 use poem_openapi::Object;
-use crate::models::get_db_objects;
+use crate::models::{error::StoreError, get_db_objects, Page};
 
 /// Get the current registry map in the store
-pub fn get_registry_map_store(path: &str) -> Option<Vec<RegistryMapStore>> {
+pub fn get_registry_map_store(path: &str) -> Result<Page<RegistryMapStore>, StoreError> {
     get_db_objects(path, COLUMN_NAME_REGISTRY_MAP_STORE)
 }
 
 /// Get the current registry config from the store
-pub fn get_registry_config_store_config(path: &str) -> Option<Vec<Config>> {
+pub fn get_registry_config_store_config(path: &str) -> Result<Page<Config>, StoreError> {
     get_db_objects(path, COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG)
 }
 
-/// Get the current users in the store
-pub fn get_registry_config_store_users(path: &str) -> Option<Vec<BTreeMap<String, User>>> {
-    get_db_objects(path, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS)
+/// Get the current users in the store, w/ password hashes redacted
+pub fn get_registry_config_store_users(path: &str) -> Result<Page<BTreeMap<String, User>>, StoreError> {
+    get_db_objects::<BTreeMap<String, User>>(path, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS).map(|users| Page {
+        items: users
+            .items
+            .into_iter()
+            .map(|u| u.into_iter().map(|(id, user)| (id, user.redacted())).collect())
+            .collect(),
+        ..users
+    })
 }
 
-const COLUMN_NAME_REGISTRY_MAP_STORE: &str = "RegistryMapStore";
-const COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG: &str = "onpremconfigstore-config";
-const COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS: &str = "onpremconfigstore-users";
+/// Replaces every password hash property in a raw json value
+pub fn redact_passwords(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if key == PASSWORD_PROPERTY {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_passwords(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_passwords),
+        _ => {}
+    }
+}
+
+/// Masks the value of every password hash property in raw json text, for records that don't parse
+///
+/// Values are masked w/ `*`, so the text keeps its length and offsets into it, ex. from a serde_json error, still apply.
+/// Returns the masked text, and each value that was masked
+pub fn mask_passwords(raw: &str) -> (String, Vec<String>) {
+    let property = format!("\"{PASSWORD_PROPERTY}\"");
+    let bytes = raw.as_bytes();
+    let mut masked = bytes.to_vec();
+    let mut values = vec![];
+
+    let mut search = 0;
+    while let Some(found) = raw[search..].find(&property) {
+        let mut start = search + found + property.len();
+        while start < bytes.len() && (bytes[start].is_ascii_whitespace() || bytes[start] == b':') {
+            start += 1;
+        }
+
+        // Only the content of a string is masked, an unterminated string is masked to the end of the text
+        let mut end = value_end(bytes, start);
+        if bytes.get(start) == Some(&b'"') {
+            start += 1;
+            if end > start && bytes[end - 1] == b'"' {
+                end -= 1;
+            }
+        }
+
+        if start < end {
+            values.push(raw[start..end].to_string());
+            masked[start..end].fill(b'*');
+        }
+        search = end.max(search + found + property.len());
+    }
+
+    (String::from_utf8_lossy(&masked).to_string(), values)
+}
+
+/// Returns the index just past the json value starting at `start`, or where the value was cut off
+fn value_end(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, b) in bytes.iter().enumerate().skip(start) {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' if depth == 0 => return i + 1,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' | b',' if depth == 0 => return i,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bytes.len()
+}
+
+/// Replaces password hashes in responses
+pub(crate) const REDACTED: &str = "<redacted>";
+/// Property of a user w/ the password hash
+const PASSWORD_PROPERTY: &str = "pwdJson";
+
+pub const COLUMN_NAME_REGISTRY_MAP_STORE: &str = "RegistryMapStore";
+pub const COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG: &str = "onpremconfigstore-config";
+pub const COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS: &str = "onpremconfigstore-users";
 
 #[allow(dead_code)]
-const COLUMN_NAME_ON_PREM_CONFIG_STORE_REPORTED_CONFIG: &str = "onpremconfigstore-reported-config";
+pub const COLUMN_NAME_ON_PREM_CONFIG_STORE_REPORTED_CONFIG: &str = "onpremconfigstore-reported-config";
 
 #[derive(Default, Object, Serialize, Deserialize, Debug)]
 pub struct RegistryMapStore {
@@ -37,6 +137,24 @@ pub struct RegistryMapStore {
     connected_registry_name: String,
 }
 
+impl RegistryMapStore {
+    pub fn children(&self) -> &[String] {
+        &self.children
+    }
+
+    pub fn connected_registry_id(&self) -> &str {
+        &self.connected_registry_id
+    }
+
+    pub fn parent_registry_id(&self) -> &str {
+        &self.parent_registry_id
+    }
+
+    pub fn connected_registry_name(&self) -> &str {
+        &self.connected_registry_name
+    }
+}
+
 #[derive(Default, Object, Serialize, Deserialize, Debug)]
 pub struct Config {
         #[serde(rename = "authModes")]
@@ -46,7 +164,7 @@ pub struct Config {
         #[serde(rename = "configRev")]
         config_revision_id: String,
         #[serde(rename = "connRegId")]
-        connected_registry_id: String,
+        pub(crate) connected_registry_id: String,
         #[serde(rename = "connRegName")]
         connected_registry_name: String,
         #[serde(rename = "connRegPath")]
@@ -115,6 +233,16 @@ pub struct User {
         user_name: String
 }
 
+impl User {
+    /// Returns the user w/ the password hash redacted
+    pub fn redacted(self) -> Self {
+        Self {
+            password_json: REDACTED.to_string(),
+            ..self
+        }
+    }
+}
+
 #[derive(Default, Object, Serialize, Deserialize, Debug)]
 pub struct Log {
         #[serde(rename = "auditLogFlag")]
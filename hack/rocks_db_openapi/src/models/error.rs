@@ -0,0 +1,194 @@
+use std::fmt::Display;
+
+use poem_openapi::{
+    payload::{Json, PlainText},
+    types::ToJSON,
+    ApiResponse, Object,
+};
+
+use serde::Serialize;
+
+use super::Page;
+
+/// Errors reading objects from a store
+#[derive(Debug)]
+pub enum StoreError {
+    /// The store does not exist at the path
+    StoreNotFound(String),
+    /// The store does not have the column family
+    ColumnFamilyNotFound { path: String, column_family: String },
+    /// The store does not have a record w/ the name
+    RecordNotFound(String),
+    /// One or more records could not be deserialized
+    InvalidRecords {
+        column_family: String,
+        errors: Vec<RecordError>,
+    },
+    /// The query parameters were not valid
+    InvalidQuery(String),
+    /// The store could not be opened or read
+    Internal(String),
+}
+
+/// A record that could not be deserialized
+#[derive(Debug, Clone, Object)]
+pub struct RecordError {
+    /// Key of the record, lossy utf8
+    pub key: String,
+    /// The property closest to where deserialization failed, if one could be found
+    pub property: Option<String>,
+    /// Up to 64 characters of the raw record, around where deserialization failed
+    pub snippet: String,
+    /// The deserialization error
+    pub message: String,
+}
+
+/// Body returned when some records could not be deserialized, w/ the records that could
+#[derive(Debug, Object)]
+pub struct PartialRecords {
+    /// The records that were deserialized
+    pub items: serde_json::Value,
+    /// An error for each record that could not be deserialized
+    pub errors: Vec<RecordError>,
+}
+
+/// Problem details body returned w/ every error response
+#[derive(Debug, Object)]
+pub struct Problem {
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Set when records could not be deserialized
+    pub errors: Vec<RecordError>,
+}
+
+/// Response for endpoints that read from a store
+#[derive(ApiResponse)]
+pub enum DbResponse<T: ToJSON> {
+    /// Paged endpoints set `X-Next-Cursor` when there are more records
+    #[oai(status = 200)]
+    Ok(Json<T>, #[oai(header = "X-Next-Cursor")] Option<String>),
+    /// Some records could not be deserialized, the records that could are returned w/ an error for each one that couldn't
+    #[oai(status = 207)]
+    Partial(Json<PartialRecords>, #[oai(header = "X-Next-Cursor")] Option<String>),
+    /// The query parameters were not valid
+    #[oai(status = 400)]
+    BadRequest(Json<Problem>),
+    /// The store, the column family, or the record does not exist
+    #[oai(status = 404)]
+    NotFound(Json<Problem>),
+    /// One or more records could not be deserialized
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<Problem>),
+    /// The store could not be opened or read
+    #[oai(status = 500)]
+    InternalServerError(Json<Problem>),
+}
+
+/// Response for endpoints that render text from a store, ex. graphviz dot
+#[derive(ApiResponse)]
+pub enum TextResponse {
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
+    #[oai(status = 400)]
+    BadRequest(Json<Problem>),
+    #[oai(status = 404)]
+    NotFound(Json<Problem>),
+    #[oai(status = 422)]
+    UnprocessableEntity(Json<Problem>),
+    #[oai(status = 500)]
+    InternalServerError(Json<Problem>),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::StoreNotFound(path) => write!(f, "store not found at {path}"),
+            StoreError::ColumnFamilyNotFound {
+                path,
+                column_family,
+            } => write!(f, "column family {column_family} not found in {path}"),
+            StoreError::InvalidRecords {
+                column_family,
+                errors,
+            } => write!(
+                f,
+                "{} record(s) in {column_family} could not be deserialized",
+                errors.len()
+            ),
+            StoreError::RecordNotFound(name) => write!(f, "{name} not found"),
+            StoreError::InvalidQuery(err) | StoreError::Internal(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<T: ToJSON> From<Result<T, StoreError>> for DbResponse<T> {
+    fn from(result: Result<T, StoreError>) -> Self {
+        match result {
+            Ok(value) => DbResponse::Ok(Json(value), None),
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl<T: ToJSON + Serialize> From<Result<Page<T>, StoreError>> for DbResponse<Vec<T>> {
+    fn from(result: Result<Page<T>, StoreError>) -> Self {
+        match result {
+            Ok(Page { items, next_cursor, errors }) if errors.is_empty() => DbResponse::Ok(Json(items), next_cursor),
+            Ok(Page { items, next_cursor, errors }) => match serde_json::to_value(&items) {
+                Ok(items) => DbResponse::Partial(Json(PartialRecords { items, errors }), next_cursor),
+                Err(err) => StoreError::Internal(format!("could not serialize records, {err}")).into(),
+            },
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl From<StoreError> for Problem {
+    fn from(err: StoreError) -> Self {
+        let (title, status, errors) = match &err {
+            StoreError::StoreNotFound(_)
+            | StoreError::ColumnFamilyNotFound { .. }
+            | StoreError::RecordNotFound(_) => ("Not Found", 404, vec![]),
+            StoreError::InvalidQuery(_) => ("Bad Request", 400, vec![]),
+            StoreError::InvalidRecords { errors, .. } => ("Unprocessable Entity", 422, errors.to_vec()),
+            StoreError::Internal(_) => ("Internal Server Error", 500, vec![]),
+        };
+
+        Problem {
+            title: title.to_string(),
+            status,
+            detail: err.to_string(),
+            errors,
+        }
+    }
+}
+
+impl<T: ToJSON> From<StoreError> for DbResponse<T> {
+    fn from(err: StoreError) -> Self {
+        let problem = Problem::from(err);
+
+        match problem.status {
+            400 => DbResponse::BadRequest(Json(problem)),
+            404 => DbResponse::NotFound(Json(problem)),
+            422 => DbResponse::UnprocessableEntity(Json(problem)),
+            _ => DbResponse::InternalServerError(Json(problem)),
+        }
+    }
+}
+
+impl From<Result<String, StoreError>> for TextResponse {
+    fn from(result: Result<String, StoreError>) -> Self {
+        let problem = match result {
+            Ok(text) => return TextResponse::Ok(PlainText(text)),
+            Err(err) => Problem::from(err),
+        };
+
+        match problem.status {
+            400 => TextResponse::BadRequest(Json(problem)),
+            404 => TextResponse::NotFound(Json(problem)),
+            422 => TextResponse::UnprocessableEntity(Json(problem)),
+            _ => TextResponse::InternalServerError(Json(problem)),
+        }
+    }
+}
@@ -1,29 +1,213 @@
+use chrono::{DateTime, FixedOffset};
 use serde::{Serialize, Deserialize};
 use poem_openapi::Object;
-use crate::models::get_db_objects;
+use crate::models::{error::StoreError, get_db_page_where, Page, PageQuery};
 
-/// Get the current registry map in the store
-pub fn list_repositories(root: &str, registry_id: &str) -> Option<Vec<Repository>> {
-    get_db_objects(format!("{}/{}", root, registry_id).as_str(), COLUMN_NAME_REPOSITORY)
+/// Returns the path of a registry's metadata store under `root`,
+///
+/// Returns an error if `registry_id` is empty, or could be used to read a store outside of `root`, ex. `..` or `a/b`
+pub fn registry_store_path(root: &str, registry_id: &str) -> Result<String, StoreError> {
+    if registry_id.is_empty()
+        || registry_id == "."
+        || registry_id == ".."
+        || registry_id.contains(['/', '\\', '\0'])
+    {
+        return Err(StoreError::InvalidQuery(format!("invalid registry_id {registry_id:?}")));
+    }
+
+    Ok(format!("{}/{}", root, registry_id))
+}
+
+/// Get the repositories in the store that match the filter
+pub fn list_repositories(root: &str, registry_id: &str, query: &PageQuery, filter: &MetadataFilter) -> Result<Page<Repository>, StoreError> {
+    let times = filter.times()?;
+    get_db_page_where(&registry_store_path(root, registry_id)?, COLUMN_NAME_REPOSITORY, query, |r: &Repository| {
+        filter.matches_repository_name(&r.repository_name)
+            && times.matches(&r.last_update_time, &r.created_time)
+    })
+}
+
+/// Get the manifest metadata in the store that match the filter
+pub fn list_repository_manifests(root: &str, registry_id: &str, query: &PageQuery, filter: &MetadataFilter) -> Result<Page<ManifestMetadata>, StoreError> {
+    let times = filter.times()?;
+    let repository_ids = repository_ids(root, registry_id, filter)?;
+
+    get_db_page_where(&registry_store_path(root, registry_id)?, COLUMN_NAME_REPOSITORY_MANIFEST_METADATA, query, |m: &ManifestMetadata| {
+        repository_ids.as_ref().is_none_or(|ids| ids.contains(&m.repository_id))
+            && filter.digest.as_ref().is_none_or(|d| d == &m.digest)
+            && times.matches(&m.last_update_time, &m.created_time)
+    })
+}
+
+/// Get the tag metadata in the store that match the filter
+pub fn list_repository_tags(root: &str, registry_id: &str, query: &PageQuery, filter: &MetadataFilter) -> Result<Page<TagMetadata>, StoreError> {
+    let times = filter.times()?;
+    let repository_ids = repository_ids(root, registry_id, filter)?;
+
+    get_db_page_where(&registry_store_path(root, registry_id)?, COLUMN_NAME_REPOSITORY_TAG_METADATA, query, |t: &TagMetadata| {
+        repository_ids.as_ref().is_none_or(|ids| ids.contains(&t.repository_id))
+            && filter.tag.as_ref().is_none_or(|p| matches_pattern(p, &t.tag))
+            && filter.digest.as_ref().is_none_or(|d| d == &t.digest)
+            && times.matches(&t.last_update_time, &t.created_time)
+    })
+}
+
+/// Get a repository by name, w/ all of its tags and manifests
+pub fn get_repository_detail(root: &str, registry_id: &str, name: &str) -> Result<RepositoryDetail, StoreError> {
+    let path = registry_store_path(root, registry_id)?;
+    let all = PageQuery::default();
+
+    let repository = get_db_page_where(&path, COLUMN_NAME_REPOSITORY, &PageQuery { limit: Some(1), ..Default::default() }, |r: &Repository| {
+        r.repository_name == name
+    })?
+    .or_invalid(COLUMN_NAME_REPOSITORY)?
+    .items
+    .pop()
+    .ok_or_else(|| StoreError::RecordNotFound(format!("repository {name}")))?;
+
+    let tags = get_db_page_where(&path, COLUMN_NAME_REPOSITORY_TAG_METADATA, &all, |t: &TagMetadata| {
+        t.repository_id == repository.repository_id
+    })?
+    .or_invalid(COLUMN_NAME_REPOSITORY_TAG_METADATA)?
+    .items;
+
+    let manifests = get_db_page_where(&path, COLUMN_NAME_REPOSITORY_MANIFEST_METADATA, &all, |m: &ManifestMetadata| {
+        m.repository_id == repository.repository_id
+    })?
+    .or_invalid(COLUMN_NAME_REPOSITORY_MANIFEST_METADATA)?
+    .items;
+
+    Ok(RepositoryDetail { repository, tags, manifests })
+}
+
+/// Returns the ids of the repositories matching the filter's repository name, or None if the filter doesn't set one
+fn repository_ids(root: &str, registry_id: &str, filter: &MetadataFilter) -> Result<Option<Vec<String>>, StoreError> {
+    if filter.repository.is_none() {
+        return Ok(None);
+    }
+
+    let repositories = get_db_page_where(&registry_store_path(root, registry_id)?, COLUMN_NAME_REPOSITORY, &PageQuery::default(), |r: &Repository| {
+        filter.matches_repository_name(&r.repository_name)
+    })?
+    .or_invalid(COLUMN_NAME_REPOSITORY)?;
+
+    Ok(Some(repositories.items.into_iter().map(|r| r.repository_id).collect()))
+}
+
+/// Filters for metadata records, filters that aren't set match every record
+///
+/// Times are RFC 3339, ex. `2022-08-01T12:00:00Z`, and are compared as times, so offsets and fractional seconds don't matter
+#[derive(Debug, Default, Clone)]
+pub struct MetadataFilter {
+    /// Repository name, `*` matches any characters
+    pub repository: Option<String>,
+    /// Tag, `*` matches any characters
+    pub tag: Option<String>,
+    /// Exact manifest digest
+    pub digest: Option<String>,
+    /// Earliest `LastUpdateTime`, inclusive
+    pub updated_after: Option<String>,
+    /// Latest `LastUpdateTime`, exclusive
+    pub updated_before: Option<String>,
+    /// Earliest `CreatedTime`, inclusive
+    pub created_after: Option<String>,
+    /// Latest `CreatedTime`, exclusive
+    pub created_before: Option<String>,
 }
 
-/// Get the current registry config from the store
-pub fn list_repository_manifests(root: &str, registry_id: &str) -> Option<Vec<ManifestMetadata>> {
-    get_db_objects(format!("{}/{}", root, registry_id).as_str(), COLUMN_NAME_REPOSITORY_MANIFEST_METADATA)
+impl MetadataFilter {
+    fn matches_repository_name(&self, repository_name: &str) -> bool {
+        self.repository.as_ref().is_none_or(|p| matches_pattern(p, repository_name))
+    }
+
+    /// Parses the time filters, returns an `InvalidQuery` error if any of them isn't an RFC 3339 time
+    fn times(&self) -> Result<TimeFilter, StoreError> {
+        let parse = |name: &str, time: &Option<String>| {
+            time.as_ref()
+                .map(|t| {
+                    DateTime::parse_from_rfc3339(t)
+                        .map_err(|e| StoreError::InvalidQuery(format!("invalid {name} {t:?}, expected an RFC 3339 time, {e}")))
+                })
+                .transpose()
+        };
+
+        Ok(TimeFilter {
+            updated_after: parse("updated_after", &self.updated_after)?,
+            updated_before: parse("updated_before", &self.updated_before)?,
+            created_after: parse("created_after", &self.created_after)?,
+            created_before: parse("created_before", &self.created_before)?,
+        })
+    }
+}
+
+/// Time filters of a `MetadataFilter`, parsed
+#[derive(Debug, Default)]
+struct TimeFilter {
+    updated_after: Option<DateTime<FixedOffset>>,
+    updated_before: Option<DateTime<FixedOffset>>,
+    created_after: Option<DateTime<FixedOffset>>,
+    created_before: Option<DateTime<FixedOffset>>,
 }
 
-/// Get the current users in the store
-pub fn list_repository_tags(root: &str, registry_id: &str) -> Option<Vec<TagMetadata>> {
-    get_db_objects(format!("{}/{}", root, registry_id).as_str(), COLUMN_NAME_REPOSITORY_TAG_METADATA)
+impl TimeFilter {
+    /// Returns true if the record's times are in range, a time that can't be parsed is never in a set range
+    fn matches(&self, last_update_time: &str, created_time: &str) -> bool {
+        let in_range = |time: &str, after: &Option<DateTime<FixedOffset>>, before: &Option<DateTime<FixedOffset>>| {
+            if after.is_none() && before.is_none() {
+                return true;
+            }
+
+            DateTime::parse_from_rfc3339(time).is_ok_and(|time| {
+                after.is_none_or(|a| time >= a) && before.is_none_or(|b| time < b)
+            })
+        };
+
+        in_range(last_update_time, &self.updated_after, &self.updated_before)
+            && in_range(created_time, &self.created_after, &self.created_before)
+    }
 }
 
-const COLUMN_NAME_REPOSITORY: &str = "Repository";
-const COLUMN_NAME_REPOSITORY_MANIFEST_METADATA: &str = "RepositoryManifestMetadata";
-const COLUMN_NAME_REPOSITORY_TAG_METADATA: &str = "RepositoryTagMetadata";
+/// Returns true if the value matches the pattern, where `*` matches any characters
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    match parts.split_last() {
+        // no wildcards, must be an exact match
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+pub const COLUMN_NAME_REPOSITORY: &str = "Repository";
+pub const COLUMN_NAME_REPOSITORY_MANIFEST_METADATA: &str = "RepositoryManifestMetadata";
+pub const COLUMN_NAME_REPOSITORY_TAG_METADATA: &str = "RepositoryTagMetadata";
 // Repository
 // RepositoryManifestMetadata
 // RepositoryTagMetadata
 
+/// A repository, w/ all of its tags and manifests
+#[derive(Object, Serialize, Debug)]
+pub struct RepositoryDetail {
+    repository: Repository,
+    tags: Vec<TagMetadata>,
+    manifests: Vec<ManifestMetadata>,
+}
+
 #[derive(Default, Object, Serialize, Deserialize, Debug)]
 pub struct Repository {
     #[serde(rename = "NoDelete")]
@@ -43,13 +227,13 @@ pub struct Repository {
     #[serde(rename = "IsNewRepository")]
     is_new_repository: bool,
     #[serde(rename = "RepositoryName")]
-    repository_name: String,
+    pub(crate) repository_name: String,
     #[serde(rename = "Locked")]
-    locked: bool,
+    pub(crate) locked: bool,
     #[serde(rename = "LockedTimestamp")]
-    locked_timestamp: String,
+    pub(crate) locked_timestamp: String,
     #[serde(rename = "RepositoryID")]
-    repository_id: String
+    pub(crate) repository_id: String
 }
 
 #[derive(Default, Object, Serialize, Deserialize, Debug)]
@@ -69,11 +253,11 @@ pub struct TagMetadata {
     #[serde(rename = "CreatedTime")]
     created_time: String,
     #[serde(rename = "RepositoryId")]
-    repository_id: String,
+    pub(crate) repository_id: String,
     #[serde(rename = "Tag")]
-    tag: String,
+    pub(crate) tag: String,
     #[serde(rename = "Digest")]
-    digest: String,
+    pub(crate) digest: String,
     #[serde(rename = "SignatureRecord")]
     signature_record: Option<String>, 
     #[serde(rename = "QuarantineState")]
@@ -97,9 +281,9 @@ pub struct ManifestMetadata {
     #[serde(rename = "CreatedTime")]
     created_time: String,
     #[serde(rename = "RepositoryId")]
-    repository_id: String,
+    pub(crate) repository_id: String,
     #[serde(rename = "Digest")]
-    digest: String,
+    pub(crate) digest: String,
     #[serde(rename = "Size")]
     size: i64,
     #[serde(rename = "ImageSize")]
@@ -120,4 +304,44 @@ pub struct ManifestMetadata {
     quarantine_details: Option<String>,
     #[serde(rename = "QuarantineState")]
     quarantine_state: Option<String> 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataFilter;
+    use crate::models::error::StoreError;
+
+    #[test]
+    fn test_times() {
+        let filter = MetadataFilter {
+            updated_after: Some("2022-08-01T12:00:00Z".to_string()),
+            created_before: Some("2022-08-01T14:00:00+02:00".to_string()),
+            ..Default::default()
+        };
+        let times = filter.times().expect("should parse");
+
+        // Same instant w/ a different offset, and w/ fractional seconds
+        assert!(times.matches("2022-08-01T08:00:00-04:00", "2022-08-01T11:59:59.9999999Z"));
+        assert!(times.matches("2022-08-01T12:00:00.5Z", "2022-08-01T11:00:00Z"));
+
+        assert!(!times.matches("2022-08-01T11:59:59Z", "2022-08-01T11:00:00Z"));
+        assert!(!times.matches("2022-08-01T13:00:00Z", "2022-08-01T12:00:00Z"));
+        assert!(!times.matches("not a time", "2022-08-01T11:00:00Z"));
+
+        // Unset ranges don't parse the record's time
+        assert!(MetadataFilter::default().times().expect("should parse").matches("", ""));
+    }
+
+    #[test]
+    fn test_invalid_time() {
+        let filter = MetadataFilter {
+            updated_before: Some("2022-08-01".to_string()),
+            ..Default::default()
+        };
+
+        match filter.times() {
+            Err(StoreError::InvalidQuery(err)) => assert!(err.starts_with("invalid updated_before"), "{err}"),
+            other => panic!("expected an invalid query, found {other:?}"),
+        }
+    }
+}
@@ -1,55 +1,311 @@
+pub mod browse;
+pub mod checks;
 pub mod config;
+pub mod error;
 pub mod metadata;
+pub mod snapshot;
+pub mod topology;
 
 use serde::{Deserialize};
-use rocksdb::{IteratorMode, Options, DB};
-use std::{str, ops::Deref};
+use rocksdb::{Direction, IteratorMode, Options, DB};
+use std::path::Path;
+
+use config::{mask_passwords, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, REDACTED};
+use error::{RecordError, StoreError};
+
+/// Length of the raw record snippet included w/ a deserialization error
+const SNIPPET_LEN: usize = 64;
+
+/// Key range, and page size, of a query against a column family
+///
+#[derive(Debug, Default, Clone)]
+pub struct PageQuery {
+    /// Maximum number of records to return, unlimited if not set
+    pub limit: Option<usize>,
+    /// Next-cursor token returned w/ a previous page
+    pub cursor: Option<String>,
+    /// Only return records whose key starts w/ this prefix
+    pub prefix: Option<String>,
+    /// First key to return, inclusive
+    pub start: Option<String>,
+    /// Last key to return, exclusive
+    pub end: Option<String>,
+}
+
+/// A page of records, w/ a token to read the next page if there are more records
+///
+/// Records that could not be deserialized are skipped, w/ an error for each one in `errors`
+#[derive(Debug)]
+pub struct Page<O> {
+    pub items: Vec<O>,
+    pub next_cursor: Option<String>,
+    pub errors: Vec<RecordError>,
+}
+
+impl<O> Page<O> {
+    /// Returns an error listing every record in `column_family` that could not be deserialized, if any
+    ///
+    /// For callers that can't return a partial result, ex. when a missing record would change the outcome
+    pub fn or_invalid(self, column_family: &str) -> Result<Self, StoreError> {
+        if self.errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(StoreError::InvalidRecords {
+                column_family: column_family.to_string(),
+                errors: self.errors,
+            })
+        }
+    }
+}
 
 /// Locates all objects in the column family: `object_name`, stored in the db at `path`
-pub fn get_db_objects<O>(path: &str, object_name: &str) -> Option<Vec<O>>
-where 
-    for<'a> O: Deserialize<'a> + Default {
+///
+/// Records that could not be deserialized are returned as errors w/ the records that could
+pub fn get_db_objects<O>(path: &str, object_name: &str) -> Result<Page<O>, StoreError>
+where
+    for<'a> O: Deserialize<'a> {
+    get_db_page(path, object_name, &PageQuery::default())
+}
+
+/// Locates a page of objects in the column family: `object_name`, stored in the db at `path`
+///
+/// The iterator seeks directly to the first key of the page, so only the records in the page are read
+pub fn get_db_page<O>(path: &str, object_name: &str, query: &PageQuery) -> Result<Page<O>, StoreError>
+where
+    for<'a> O: Deserialize<'a> {
+    get_db_page_where(path, object_name, query, |_| true)
+}
+
+/// Locates a page of objects in the column family: `object_name`, that match `predicate`
+///
+/// Only matching objects, and records that could not be deserialized, count towards the page limit
+pub fn get_db_page_where<O>(
+    path: &str,
+    object_name: &str,
+    query: &PageQuery,
+    predicate: impl Fn(&O) -> bool,
+) -> Result<Page<O>, StoreError>
+where
+    for<'a> O: Deserialize<'a> {
+    let mut items = vec![];
+    let mut errors = vec![];
+    let next_cursor = scan(path, object_name, query, |k, v| match serde_json::from_slice::<O>(v) {
+        Ok(value) if predicate(&value) => {
+            items.push(value);
+            true
+        }
+        Ok(_) => false,
+        Err(err) => {
+            errors.push(record_error(object_name, k, v, &err));
+            true
+        }
+    })?;
+
+    Ok(Page { items, next_cursor, errors })
+}
+
+/// Visits the raw key/value pairs in a page of the column family: `object_name`, returning the next-cursor token if there are more records
+///
+/// `visit` returns true if the record counts towards the page limit
+pub fn scan(
+    path: &str,
+    object_name: &str,
+    query: &PageQuery,
+    mut visit: impl FnMut(&[u8], &[u8]) -> bool,
+) -> Result<Option<String>, StoreError> {
+    let cursor = match query.cursor.as_ref() {
+        Some(cursor) => Some(decode_cursor(cursor)?),
+        None => None,
+    };
+
+    let prefix = query.prefix.as_ref().map(|p| p.as_bytes().to_vec()).unwrap_or_default();
+    let end = query.end.as_ref().map(|e| e.as_bytes().to_vec());
+
+    // Seek to the furthest of the cursor, start, and prefix
+    let seek = [
+        cursor,
+        query.start.as_ref().map(|s| s.as_bytes().to_vec()),
+        Some(prefix.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .max()
+    .unwrap_or_default();
+
+    let (opened, _) = open_db(path)?;
+    let handle = opened
+        .cf_handle(object_name)
+        .ok_or(StoreError::ColumnFamilyNotFound {
+            path: path.to_string(),
+            column_family: object_name.to_string(),
+        })?;
+
+    let mut count = 0;
+    for (k, v) in opened.iterator_cf(handle, IteratorMode::From(&seek, Direction::Forward)) {
+        if !k.starts_with(&prefix) || end.as_ref().is_some_and(|end| k.as_ref() >= end.as_slice()) {
+            break;
+        }
+
+        if query.limit.is_some_and(|limit| count >= limit) {
+            return Ok(Some(to_hex(&k)));
+        }
+
+        if visit(&k, &v) {
+            count += 1;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Opens the db at `path` for reading, returning the db and the names of its column families
+pub fn open_db(path: &str) -> Result<(DB, Vec<String>), StoreError> {
+    if !Path::new(path).exists() {
+        return Err(StoreError::StoreNotFound(path.to_string()));
+    }
+
     let db_opts = Options::default();
-    let cfs = DB::list_cf(&db_opts, &path).unwrap();
+    let cfs = DB::list_cf(&db_opts, &path)
+        .map_err(|e| StoreError::Internal(format!("could not list column families in {path}, {e}")))?;
 
     let cf_opts = Options::default();
-    {
-        let cf = DB::open_cf_for_read_only(&cf_opts, &path, &cfs, false);
-
-        match cf {
-            Ok(opened) => {
-                let mut response = vec![];
-                let handle = opened.cf_handle(object_name).unwrap();
-
-                opened.iterator_cf(handle, IteratorMode::Start).for_each(|(_, v)| {
-
-                    let value = serde_json::from_slice(&v);
-
-                    let value: O = match value {
-                        Ok(v) => v, 
-                        Err(err) => {
-                            let raw_json = str::from_utf8(&v).unwrap_or_default(); 
-                            let property = raw_json[..err.column()]
-                                .split("\"")
-                                .collect::<Vec<&str>>().iter()
-                                    .rev()
-                                    .skip(1)
-                                    .next()
-                                    .unwrap()
-                                    .deref()
-                                    .to_owned();
-
-                            eprintln!("{}\nproperty_key: {}", err, property);
-                            O::default()
-                        }
-                    };
-
-                    response.push(value);
-                });
-
-                return Some(response)
-            },
-            Err(_) => None 
+    let opened = DB::open_cf_for_read_only(&cf_opts, &path, &cfs, false)
+        .map_err(|e| StoreError::Internal(format!("could not open {path}, {e}")))?;
+
+    Ok((opened, cfs))
+}
+
+/// Encodes bytes as lowercase hex, used for next-cursor tokens and binary values
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes a next-cursor token back into the key it was created from
+fn decode_cursor(cursor: &str) -> Result<Vec<u8>, StoreError> {
+    let invalid = || StoreError::InvalidQuery(format!("invalid cursor {cursor}"));
+
+    if !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+
+    (0..cursor.len())
+        .step_by(2)
+        .map(|i| {
+            cursor
+                .get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Describes a record that failed to deserialize, w/ the property and raw snippet closest to where it failed
+///
+/// Password hashes are masked before the snippet is taken, and removed from the message. A users record w/o a password
+/// property that could be masked, ex. if the property name itself is damaged, is never included in the snippet
+fn record_error(object_name: &str, key: &[u8], value: &[u8], err: &serde_json::Error) -> RecordError {
+    let (raw_json, passwords) = mask_passwords(&String::from_utf8_lossy(value));
+
+    // serde_json reports a 1-based line, and a 1-based column within that line
+    let offset = raw_json
+        .split_inclusive('\n')
+        .take(err.line().saturating_sub(1))
+        .map(|l| l.len())
+        .sum::<usize>()
+        + err.column();
+    let offset = floor_char_boundary(&raw_json, offset);
+
+    let before = &raw_json[..offset];
+    let property = before
+        .rsplit('"')
+        .nth(1)
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_string());
+
+    let start = floor_char_boundary(&raw_json, offset.saturating_sub(SNIPPET_LEN / 2));
+    let end = floor_char_boundary(&raw_json, start + SNIPPET_LEN);
+    let snippet = if object_name == COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS && passwords.is_empty() {
+        REDACTED.to_string()
+    } else {
+        raw_json[start..end].to_string()
+    };
+
+    // serde_json quotes the unescaped value in messages, ex. `invalid type: string "..."`
+    let mut message = err.to_string();
+    for password in passwords {
+        let unescaped = serde_json::from_str::<String>(&format!("\"{password}\"")).ok();
+        let debug = unescaped.as_ref().map(|u| {
+            let debug = format!("{u:?}");
+            debug[1..debug.len() - 1].to_string()
+        });
+        for value in [Some(password), unescaped, debug].into_iter().flatten().filter(|v| !v.is_empty()) {
+            message = message.replace(&value, REDACTED);
         }
     }
+
+    RecordError {
+        key: String::from_utf8_lossy(key).to_string(),
+        property,
+        snippet,
+        message,
+    }
+}
+
+/// Returns the closest char boundary at or before index, clamped to the length of text
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{config::User, record_error};
+    use crate::models::config::{COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG, COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS};
+
+    const SECRET: &str = "s3cr3t-hash";
+
+    /// Returns the error body for a users record that fails to deserialize
+    fn user_error(record: &str) -> String {
+        let err = serde_json::from_str::<BTreeMap<String, User>>(record).expect_err("record should be malformed");
+        format!("{:?}", record_error(COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, b"users", record.as_bytes(), &err))
+    }
+
+    #[test]
+    fn test_record_error_redacts_passwords() {
+        // userName is not a string, the snippet around it includes the password hash
+        let body = user_error(&format!(
+            r#"{{"u1":{{"connRegId":"c","parentRegId":"p","permJsons":[],"pwdJson":"{{\"hash\":\"{SECRET}\"}}","userId":"u1","userName":5}}}}"#
+        ));
+        assert!(!body.contains(SECRET), "{body}");
+        assert!(body.contains("userName"), "{body}");
+
+        // pwdJson itself has the wrong type, serde_json includes the value in the message
+        let body = user_error(&format!(r#"{{"u1":{{"pwdJson":["{SECRET}"],"userId":"u1"}}}}"#));
+        assert!(!body.contains(SECRET), "{body}");
+        let body = user_error(r#"{"u1":{"pwdJson":1234567890,"userId":"u1"}}"#);
+        assert!(!body.contains("1234567890"), "{body}");
+
+        // The record is cut off in the middle of the password hash
+        let body = user_error(&format!(r#"{{"u1":{{"userId":"u1","pwdJson":"{{\"hash\":\"{SECRET}"#));
+        assert!(!body.contains(SECRET), "{body}");
+
+        // The property name is damaged, so nothing can be masked
+        let body = user_error(&format!(r#"{{"u1":{{"userId":"u1","pwdJs\u0000on":"{SECRET}"#));
+        assert!(!body.contains(SECRET), "{body}");
+    }
+
+    #[test]
+    fn test_record_error_snippet() {
+        let record = r#"{"authModes":[],"configRev":5}"#;
+        let err = serde_json::from_str::<super::config::Config>(record).expect_err("record should be malformed");
+        let error = record_error(COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG, b"config", record.as_bytes(), &err);
+
+        assert_eq!(error.property.as_deref(), Some("configRev"));
+        assert_eq!(error.snippet, record);
+    }
 }
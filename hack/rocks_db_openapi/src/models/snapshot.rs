@@ -0,0 +1,318 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use poem_openapi::Object;
+use rocksdb::IteratorMode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::{
+    config::{
+        Config, RegistryMapStore, User, COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG,
+        COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS, COLUMN_NAME_REGISTRY_MAP_STORE,
+    },
+    error::StoreError,
+    metadata::{
+        ManifestMetadata, Repository, TagMetadata, COLUMN_NAME_REPOSITORY,
+        COLUMN_NAME_REPOSITORY_MANIFEST_METADATA, COLUMN_NAME_REPOSITORY_TAG_METADATA,
+    },
+    open_db, to_hex,
+};
+
+/// A line of a snapshot, one record of a column family
+///
+/// Snapshots are deterministic, column families are written in name order, keys in the order they are stored, and json properties are sorted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotRecord {
+    pub column_family: String,
+    /// The key, lossy utf8
+    pub key: String,
+    /// The key as hex
+    pub key_hex: String,
+    /// `json` if the value parsed as json, otherwise `hex`
+    pub format: String,
+    /// The value as json, or a hex string
+    pub value: Value,
+}
+
+/// Two json-lines snapshots to diff
+#[derive(Object, Deserialize, Debug)]
+pub struct SnapshotDiffRequest {
+    pub before: String,
+    pub after: String,
+}
+
+/// Differences between two snapshots
+#[derive(Object, Serialize, Debug, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<RecordChange>,
+    pub removed: Vec<RecordChange>,
+    pub changed: Vec<RecordChange>,
+}
+
+/// A record that was added, removed, or changed between two snapshots
+#[derive(Object, Serialize, Debug)]
+pub struct RecordChange {
+    pub column_family: String,
+    pub key: String,
+    /// Properties that changed, only set for changed records
+    pub fields: Vec<FieldChange>,
+}
+
+/// A property that changed, values are json
+#[derive(Object, Serialize, Debug)]
+pub struct FieldChange {
+    /// Path to the property, ex. `parent.syncProperties.schedule`
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Writes a json-lines snapshot of every column family in the store at `path`
+pub fn export_snapshot(path: &str, mut output: impl Write) -> Result<usize, StoreError> {
+    let (opened, mut cfs) = open_db(path)?;
+    cfs.sort();
+
+    let mut count = 0;
+    for column_family in cfs {
+        let handle = match opened.cf_handle(&column_family) {
+            Some(handle) => handle,
+            None => continue,
+        };
+
+        for (k, v) in opened.iterator_cf(handle, IteratorMode::Start) {
+            let (format, value) = match serde_json::from_slice::<Value>(&v) {
+                Ok(value) => ("json", value),
+                Err(_) => ("hex", Value::String(to_hex(&v))),
+            };
+
+            let record = SnapshotRecord {
+                column_family: column_family.to_string(),
+                key: String::from_utf8_lossy(&k).to_string(),
+                key_hex: to_hex(&k),
+                format: format.to_string(),
+                value,
+            };
+
+            let line = serde_json::to_string(&record)
+                .map_err(|e| StoreError::Internal(format!("could not serialize record, {e}")))?;
+            writeln!(output, "{line}")
+                .map_err(|e| StoreError::Internal(format!("could not write snapshot, {e}")))?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Parses a json-lines snapshot, keyed by column family and key
+pub fn parse_snapshot(
+    snapshot: &str,
+) -> Result<BTreeMap<(String, String), SnapshotRecord>, StoreError> {
+    let mut records = BTreeMap::new();
+    for (number, line) in snapshot.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let record = serde_json::from_str::<SnapshotRecord>(line).map_err(|e| {
+            StoreError::InvalidQuery(format!("could not parse snapshot line {}, {e}", number + 1))
+        })?;
+
+        records.insert((record.column_family.to_string(), record.key_hex.to_string()), record);
+    }
+
+    Ok(records)
+}
+
+/// Diffs two json-lines snapshots
+///
+/// Changed records of known models are compared as the model, so only the model's properties are reported
+pub fn diff_snapshots(before: &str, after: &str) -> Result<SnapshotDiff, StoreError> {
+    let before = parse_snapshot(before)?;
+    let mut after = parse_snapshot(after)?;
+    let mut diff = SnapshotDiff::default();
+
+    for (key, before) in before {
+        match after.remove(&key) {
+            Some(after) if after.value != before.value => {
+                let mut fields = vec![];
+                diff_values(
+                    "",
+                    &normalize(&before.column_family, before.value.clone()),
+                    &normalize(&after.column_family, after.value.clone()),
+                    &mut fields,
+                );
+
+                diff.changed.push(RecordChange {
+                    column_family: before.column_family,
+                    key: before.key,
+                    fields,
+                });
+            }
+            Some(_) => continue,
+            None => diff.removed.push(RecordChange {
+                column_family: before.column_family,
+                key: before.key,
+                fields: vec![],
+            }),
+        }
+    }
+
+    for (_, added) in after {
+        diff.added.push(RecordChange {
+            column_family: added.column_family,
+            key: added.key,
+            fields: vec![],
+        });
+    }
+
+    Ok(diff)
+}
+
+/// Round trips values of known column families through their model, otherwise returns the value as is
+fn normalize(column_family: &str, value: Value) -> Value {
+    fn through<O>(value: Value) -> Value
+    where
+        for<'a> O: Deserialize<'a> + Serialize,
+    {
+        serde_json::from_value::<O>(value.clone())
+            .and_then(serde_json::to_value)
+            .unwrap_or(value)
+    }
+
+    match column_family {
+        COLUMN_NAME_ON_PREM_CONFIG_STORE_CONFIG => through::<Config>(value),
+        COLUMN_NAME_REGISTRY_MAP_STORE => through::<RegistryMapStore>(value),
+        COLUMN_NAME_ON_PREM_CONFIG_STORE_USERS => through::<BTreeMap<String, User>>(value),
+        COLUMN_NAME_REPOSITORY => through::<Repository>(value),
+        COLUMN_NAME_REPOSITORY_TAG_METADATA => through::<TagMetadata>(value),
+        COLUMN_NAME_REPOSITORY_MANIFEST_METADATA => through::<ManifestMetadata>(value),
+        _ => value,
+    }
+}
+
+/// Collects the properties that differ between two json values, nested objects are compared property by property
+fn diff_values(path: &str, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => {
+            let keys = b.keys().chain(a.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                };
+
+                match (b.get(key), a.get(key)) {
+                    (Some(b), Some(a)) => diff_values(&field, b, a, changes),
+                    (b, a) => changes.push(FieldChange {
+                        field,
+                        before: b.map(|b| b.to_string()),
+                        after: a.map(|a| a.to_string()),
+                    }),
+                }
+            }
+        }
+        (b, a) if b != a => changes.push(FieldChange {
+            field: path.to_string(),
+            before: Some(b.to_string()),
+            after: Some(a.to_string()),
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{diff_snapshots, normalize, parse_snapshot, SnapshotRecord};
+    use crate::models::{config::COLUMN_NAME_REGISTRY_MAP_STORE, to_hex};
+
+    /// Returns a snapshot line for a json record
+    fn line(column_family: &str, key: &str, value: serde_json::Value) -> String {
+        serde_json::to_string(&SnapshotRecord {
+            column_family: column_family.to_string(),
+            key: key.to_string(),
+            key_hex: to_hex(key.as_bytes()),
+            format: "json".to_string(),
+            value,
+        })
+        .expect("should serialize")
+    }
+
+    fn registry(id: &str, parent: &str, children: &[&str]) -> serde_json::Value {
+        json!({
+            "Children": children,
+            "ConnectedRegistryId": id,
+            "ParentRegistryId": parent,
+            "ConnectedRegistryName": format!("{id}-name"),
+        })
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let before = [
+            line("custom", "a", json!({ "value": 1 })),
+            line("custom", "b", json!({ "value": 1 })),
+            line("custom", "c", json!({ "value": 1 })),
+        ]
+        .join("\n");
+        let after = [
+            line("custom", "b", json!({ "value": 2 })),
+            line("custom", "c", json!({ "value": 1 })),
+            line("custom", "d", json!({ "value": 1 })),
+        ]
+        .join("\n");
+
+        let diff = diff_snapshots(&before, &after).expect("should diff");
+        assert_eq!(diff.removed.iter().map(|r| r.key.as_str()).collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(diff.added.iter().map(|r| r.key.as_str()).collect::<Vec<_>>(), vec!["d"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].key, "b");
+        assert_eq!(diff.changed[0].fields.len(), 1);
+        assert_eq!(diff.changed[0].fields[0].field, "value");
+        assert_eq!(diff.changed[0].fields[0].before.as_deref(), Some("1"));
+        assert_eq!(diff.changed[0].fields[0].after.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_diff_changed_nested_field() {
+        let before = line("custom", "a", json!({ "parent": { "sync": { "schedule": "* * * * *", "window": "PT1H" } } }));
+        let after = line("custom", "a", json!({ "parent": { "sync": { "schedule": "0 * * * *", "window": "PT1H" } } }));
+
+        let diff = diff_snapshots(&before, &after).expect("should diff");
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+
+        let fields = &diff.changed[0].fields;
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].field, "parent.sync.schedule");
+        assert_eq!(fields[0].before.as_deref(), Some("\"* * * * *\""));
+        assert_eq!(fields[0].after.as_deref(), Some("\"0 * * * *\""));
+    }
+
+    #[test]
+    fn test_diff_normalizes_known_models() {
+        // Properties the model doesn't have are dropped when the record is normalized, so they aren't reported
+        let mut with_extra = registry("child", "parent", &[]);
+        with_extra["Unknown"] = json!("ignored");
+        let before = line(COLUMN_NAME_REGISTRY_MAP_STORE, "child", registry("child", "parent", &[]));
+        let after = line(COLUMN_NAME_REGISTRY_MAP_STORE, "child", with_extra.clone());
+
+        let diff = diff_snapshots(&before, &after).expect("should diff");
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.changed[0].fields.is_empty(), "{:?}", diff.changed[0].fields);
+
+        assert_eq!(normalize(COLUMN_NAME_REGISTRY_MAP_STORE, with_extra), registry("child", "parent", &[]));
+        assert_eq!(normalize("custom", json!({ "Unknown": 1 })), json!({ "Unknown": 1 }));
+    }
+
+    #[test]
+    fn test_parse_snapshot() {
+        let snapshot = format!("{}\n\n{}\n", line("custom", "a", json!(1)), line("other", "a", json!(2)));
+        let records = parse_snapshot(&snapshot).expect("should parse");
+        assert_eq!(records.len(), 2);
+
+        let err = parse_snapshot("{}\nnot json").expect_err("should not parse");
+        assert!(err.to_string().contains("line 1"), "{err}");
+    }
+}
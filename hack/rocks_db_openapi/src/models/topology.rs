@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use poem_openapi::Object;
+use serde::Serialize;
+
+use crate::models::{
+    config::{get_registry_map_store, RegistryMapStore, COLUMN_NAME_REGISTRY_MAP_STORE},
+    error::StoreError,
+};
+
+/// Builds the connected registry hierarchy from the registry map in the config store at `path`
+///
+/// Fails if any registry could not be deserialized, since a missing registry would be reported as an orphan
+pub fn get_registry_topology(path: &str) -> Result<Topology, StoreError> {
+    get_registry_map_store(path)
+        .and_then(|map| map.or_invalid(COLUMN_NAME_REGISTRY_MAP_STORE))
+        .map(|map| Topology::new(&map.items))
+}
+
+/// Connected registry hierarchy, built from the parent of each registry in the registry map
+///
+/// Registries whose parent is not in the map, ex. the cloud registry, are roots
+#[derive(Object, Serialize, Debug, Default)]
+pub struct Topology {
+    /// Every registry reachable from a root, depth-first w/ children sorted by name
+    pub nodes: Vec<RegistryNode>,
+    /// Parents that are not in the map, ex. the cloud registry
+    pub external: Vec<String>,
+    /// Registries whose parent and children don't agree, or that are listed as a child but are not in the map
+    pub orphans: Vec<String>,
+    /// Chains of registries that are each other's parent, these are not reachable from a root
+    pub cycles: Vec<Vec<String>>,
+    /// Registries below a cycle that are not part of it, so they are not reachable from a root either,
+    /// `depth` is the distance from the cycle
+    pub detached: Vec<RegistryNode>,
+}
+
+/// A connected registry in the hierarchy
+#[derive(Object, Serialize, Debug)]
+pub struct RegistryNode {
+    pub id: String,
+    pub name: String,
+    /// Not set for roots w/o a parent
+    pub parent: Option<String>,
+    /// 0 for roots
+    pub depth: u32,
+    pub children: Vec<String>,
+}
+
+impl Topology {
+    pub fn new(map: &[RegistryMapStore]) -> Self {
+        let registries = map
+            .iter()
+            .map(|r| (r.connected_registry_id(), r))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut children = BTreeMap::<&str, Vec<&RegistryMapStore>>::new();
+        for registry in map {
+            children.entry(registry.parent_registry_id()).or_default().push(registry);
+        }
+        for list in children.values_mut() {
+            list.sort_by_key(|r| (r.connected_registry_name(), r.connected_registry_id()));
+        }
+
+        let mut topology = Topology::default();
+
+        // Parent and child links have to agree, and every child has to be in the map
+        let mut orphans = BTreeSet::new();
+        for registry in map {
+            let parent = registries.get(registry.parent_registry_id());
+            if parent.is_some_and(|p| !p.children().iter().any(|c| c == registry.connected_registry_id())) {
+                orphans.insert(registry.connected_registry_id().to_string());
+            }
+
+            for child in registry.children() {
+                match registries.get(child.as_str()) {
+                    Some(c) if c.parent_registry_id() == registry.connected_registry_id() => {}
+                    _ => {
+                        orphans.insert(child.to_string());
+                    }
+                }
+            }
+        }
+        topology.orphans = orphans.into_iter().collect();
+
+        let mut roots = map
+            .iter()
+            .filter(|r| !registries.contains_key(r.parent_registry_id()))
+            .collect::<Vec<_>>();
+        roots.sort_by_key(|r| (r.connected_registry_name(), r.connected_registry_id()));
+
+        topology.external = roots
+            .iter()
+            .map(|r| r.parent_registry_id())
+            .filter(|p| !p.is_empty())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|p| p.to_string())
+            .collect();
+
+        let mut visited = BTreeSet::new();
+        let mut stack = roots.into_iter().rev().map(|r| (r, 0)).collect::<Vec<_>>();
+        while let Some((registry, depth)) = stack.pop() {
+            if !visited.insert(registry.connected_registry_id()) {
+                continue;
+            }
+
+            let registry_children = children
+                .get(registry.connected_registry_id())
+                .cloned()
+                .unwrap_or_default();
+
+            topology.nodes.push(RegistryNode {
+                id: registry.connected_registry_id().to_string(),
+                name: registry.connected_registry_name().to_string(),
+                parent: Some(registry.parent_registry_id().to_string()).filter(|p| !p.is_empty()),
+                depth,
+                children: registry_children
+                    .iter()
+                    .map(|c| c.connected_registry_id().to_string())
+                    .collect(),
+            });
+
+            stack.extend(registry_children.into_iter().rev().map(|c| (c, depth + 1)));
+        }
+
+        // Registries that aren't reachable from a root are in, or below, a cycle
+        let mut detached = BTreeMap::<&str, u32>::new();
+        for registry in map {
+            let mut chain: Vec<&str> = vec![];
+            let mut current = registry.connected_registry_id();
+            while !visited.contains(current) {
+                if let Some(start) = chain.iter().position(|c| *c == current) {
+                    for (i, below) in chain[..start].iter().enumerate() {
+                        detached.insert(below, (start - i) as u32);
+                    }
+
+                    let mut cycle = chain[start..].iter().map(|c| c.to_string()).collect::<Vec<_>>();
+                    // rotate so the same cycle is always reported the same way
+                    if let Some(min) = cycle.iter().enumerate().min_by_key(|(_, c)| *c).map(|(i, _)| i) {
+                        cycle.rotate_left(min);
+                    }
+                    if !topology.cycles.contains(&cycle) {
+                        topology.cycles.push(cycle);
+                    }
+                    break;
+                }
+
+                chain.push(current);
+                current = match registries.get(current) {
+                    Some(r) => r.parent_registry_id(),
+                    None => break,
+                };
+            }
+        }
+
+        topology.detached = detached
+            .into_iter()
+            .filter_map(|(id, depth)| registries.get(id).map(|r| (r, depth)))
+            .map(|(registry, depth)| RegistryNode {
+                id: registry.connected_registry_id().to_string(),
+                name: registry.connected_registry_name().to_string(),
+                parent: Some(registry.parent_registry_id().to_string()),
+                depth,
+                children: children
+                    .get(registry.connected_registry_id())
+                    .map(|list| list.iter().map(|c| c.connected_registry_id().to_string()).collect())
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        topology
+    }
+
+    /// Renders the topology as a graphviz digraph, w/ external parents dashed, orphans orange, and cycles and registries below them red
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph registries {\n    rankdir=TB;\n");
+
+        for external in &self.external {
+            dot.push_str(&format!("    \"{0}\" [label=\"{0}\", style=dashed];\n", escape(external)));
+        }
+
+        for (node, color) in self
+            .nodes
+            .iter()
+            .map(|n| (n, if self.orphans.contains(&n.id) { ", color=orange" } else { "" }))
+            .chain(self.detached.iter().map(|n| (n, ", color=red")))
+        {
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\\n{}\"{color}];\n",
+                escape(&node.id),
+                escape(&node.name),
+                escape(&node.id)
+            ));
+
+            if let Some(parent) = node.parent.as_ref() {
+                dot.push_str(&format!("    \"{}\" -> \"{}\";\n", escape(parent), escape(&node.id)));
+            }
+        }
+
+        for cycle in &self.cycles {
+            for (i, id) in cycle.iter().enumerate() {
+                let parent = &cycle[(i + 1) % cycle.len()];
+                dot.push_str(&format!("    \"{}\" [color=red];\n", escape(id)));
+                dot.push_str(&format!("    \"{}\" -> \"{}\" [color=red];\n", escape(parent), escape(id)));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Escapes a value for a quoted DOT id or label, `"` and `\` are the only characters that need it
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Topology;
+    use crate::models::config::RegistryMapStore;
+
+    fn registry(id: &str, parent: &str, children: &[&str]) -> RegistryMapStore {
+        serde_json::from_value(json!({
+            "Children": children,
+            "ConnectedRegistryId": id,
+            "ParentRegistryId": parent,
+            "ConnectedRegistryName": id,
+        }))
+        .expect("should deserialize")
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let topology = Topology::new(&[
+            registry("b", "a", &[]),
+            registry("a", "cloud", &["c", "b"]),
+            registry("c", "a", &[]),
+        ]);
+
+        let nodes = topology
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.parent.as_deref(), n.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(nodes, vec![("a", Some("cloud"), 0), ("b", Some("a"), 1), ("c", Some("a"), 1)]);
+        assert_eq!(topology.nodes[0].children, vec!["b", "c"]);
+        assert_eq!(topology.external, vec!["cloud"]);
+        assert!(topology.orphans.is_empty());
+        assert!(topology.cycles.is_empty());
+    }
+
+    #[test]
+    fn test_two_node_cycle() {
+        let topology = Topology::new(&[
+            registry("root", "", &[]),
+            registry("y", "x", &["x"]),
+            registry("x", "y", &["y"]),
+        ]);
+
+        assert_eq!(topology.cycles, vec![vec!["x".to_string(), "y".to_string()]]);
+        assert_eq!(topology.nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["root"]);
+        assert!(topology.external.is_empty());
+        assert!(topology.to_dot().contains("\"y\" -> \"x\" [color=red];"));
+    }
+
+    #[test]
+    fn test_parent_children_disagree() {
+        let topology = Topology::new(&[
+            // a doesn't list b as a child, and lists c which is not in the map
+            registry("a", "cloud", &["c"]),
+            registry("b", "a", &[]),
+        ]);
+
+        assert_eq!(topology.orphans, vec!["b", "c"]);
+        assert!(topology.cycles.is_empty());
+
+        // b is still placed under its parent
+        assert_eq!(topology.nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(topology.to_dot().contains("\"b\" [label=\"b\\nb\", color=orange];"));
+    }
+
+    #[test]
+    fn test_below_cycle() {
+        let topology = Topology::new(&[
+            registry("root", "", &[]),
+            registry("x", "y", &["y", "z"]),
+            registry("y", "x", &["x"]),
+            // z and w hang below the cycle, but are not part of it
+            registry("z", "x", &["w"]),
+            registry("w", "z", &[]),
+        ]);
+
+        assert_eq!(topology.cycles, vec![vec!["x".to_string(), "y".to_string()]]);
+        assert_eq!(topology.nodes.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(), vec!["root"]);
+
+        let detached = topology
+            .detached
+            .iter()
+            .map(|n| (n.id.as_str(), n.parent.as_deref(), n.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(detached, vec![("w", Some("z"), 2), ("z", Some("x"), 1)]);
+        assert!(topology.to_dot().contains("\"x\" -> \"z\";"));
+    }
+
+    #[test]
+    fn test_dot_escape() {
+        let topology = Topology::new(&[registry("a\"b\\c", "cloud", &[])]);
+
+        let dot = topology.to_dot();
+        assert!(dot.contains("    \"a\\\"b\\\\c\" [label=\"a\\\"b\\\\c\\na\\\"b\\\\c\"];"), "{dot}");
+        assert!(dot.contains("    \"cloud\" -> \"a\\\"b\\\\c\";"), "{dot}");
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use lifec::plugins::Project;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::{
+    fetch::RemoteSource,
+    install::Install,
+    resources::{Origin, Resolver, PROJECT_BLOCK},
+};
+
+/// Name of the manifest file at the root of a lab archive
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Version of the manifest format
+const MANIFEST_VERSION: u32 = 1;
+
+/// Directory in an imported lab that referenced resources are unpacked to
+const OVERLAY_DIR_NAME: &str = "overlay";
+
+/// Describes the contents of a lab archive
+///
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Version of the manifest format
+    pub version: u32,
+    /// Name of the lab, ex. `azure`
+    pub name: String,
+    /// The first heading of the lab's `.runmd`, ex. `Azure development`
+    #[serde(default)]
+    pub overview: Option<String>,
+    /// Seconds since the unix epoch when the lab was exported
+    pub created_at: u64,
+    /// Every file in the archive
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Describes a single file in a lab archive
+///
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    /// Path of the file in the archive, `lab/{path}` for files in the lab folder, `resources/{path}` for referenced resources
+    pub path: String,
+    /// Where the file was resolved from when the lab was exported, `overlay`, `filesystem` or `embedded`
+    pub source: String,
+    /// Hex encoded sha256 of the file
+    pub sha256: String,
+}
+
+/// Packages a lab, and every resource it references, into a portable tar.gz archive
+///
+/// A lab archive contains,
+/// - `manifest.json`, the name of the lab and the sha256 of every file
+/// - `lab/`, the lab's `.runmd` and every asset in the lab's folder, ex. `portal.js`
+/// - `resources/`, every `file_src`, `lib/...` and `part` resource the `.runmd` references, by virtual path
+///
+/// Importing a lab unpacks `lab/` to `{lab_dir}/{name}`, and `resources/` to `{lab_dir}/{name}/overlay`. If the lab
+/// does not set one already, a `chiron resources` block w/ the absolute path of the overlay is added to the `.runmd`
+pub struct Archive;
+
+impl Archive {
+    /// Exports a lab to a tar.gz archive at `output`, returns the manifest that was written
+    ///
+    /// The lab can be a path to a .runmd file, the name of a lab in `lab_dir`, or the name of an embedded lab
+    pub fn export(
+        lab: impl AsRef<str>,
+        lab_dir: Option<String>,
+        output: impl AsRef<Path>,
+    ) -> Result<Manifest, String> {
+        let (manifest, archive) = Self::package(lab, lab_dir)?;
+
+        std::fs::write(output.as_ref(), archive)
+            .map_err(|e| format!("could not write {:?}, {e}", output.as_ref()))?;
+
+        Ok(manifest)
+    }
+
+    /// Packages a lab as a tar.gz archive, returns the manifest and the content of the archive
+    pub fn package(lab: impl AsRef<str>, lab_dir: Option<String>) -> Result<(Manifest, Vec<u8>), String> {
+        let (name, files) = Self::collect(lab, lab_dir)?;
+
+        let overview = files
+            .get("lab/.runmd")
+            .and_then(|(content, _)| std::str::from_utf8(content).ok())
+            .and_then(|runmd| {
+                runmd
+                    .lines()
+                    .find_map(|l| l.strip_prefix("# "))
+                    .map(|h| h.trim().to_string())
+            });
+
+        let mut manifest = Manifest {
+            version: MANIFEST_VERSION,
+            name,
+            overview,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            files: vec![],
+        };
+
+        for (path, (content, origin)) in files.iter() {
+            manifest.files.push(ManifestEntry {
+                path: path.to_string(),
+                source: origin.as_str().to_string(),
+                sha256: Install::sha256(content),
+            });
+        }
+
+        let mut builder = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+
+        let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+        Self::append(&mut builder, MANIFEST_FILE_NAME, &manifest_json)?;
+
+        for (path, (content, _)) in files.iter() {
+            Self::append(&mut builder, path, content)?;
+        }
+
+        let archive = builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(|e| format!("could not package {}, {e}", manifest.name))?;
+
+        Ok((manifest, archive))
+    }
+
+    /// Imports a lab archive into `lab_dir`, returns the manifest of the imported lab
+    ///
+    /// Every file is verified against the manifest before anything is written. If the lab already exists in `lab_dir`,
+    /// the import fails unless `force` is set, in which case the existing lab folder is replaced
+    pub fn import(
+        archive: impl AsRef<Path>,
+        lab_dir: impl AsRef<Path>,
+        force: bool,
+    ) -> Result<Manifest, String> {
+        let file = File::open(archive.as_ref())
+            .map_err(|e| format!("could not open {:?}, {e}", archive.as_ref()))?;
+
+        Self::import_from(file, lab_dir, force)
+    }
+
+    /// Imports a lab archive read from `reader` into `lab_dir`, returns the manifest of the imported lab
+    pub fn import_from(
+        reader: impl Read,
+        lab_dir: impl AsRef<Path>,
+        force: bool,
+    ) -> Result<Manifest, String> {
+        let (manifest, files) = Self::unpack(reader)?;
+
+        let lab_path = lab_dir.as_ref().join(&manifest.name);
+        if lab_path.exists() && !force {
+            return Err(format!(
+                "{:?} already exists, use force to replace it",
+                lab_path
+            ));
+        }
+
+        // Files from the previous import are removed, so that files no longer in the lab don't linger
+        if lab_path.exists() {
+            std::fs::remove_dir_all(&lab_path)
+                .map_err(|e| format!("could not remove {:?}, {e}", lab_path))?;
+        }
+
+        let overlay_path = lab_path.join(OVERLAY_DIR_NAME);
+        for (path, content) in files.iter() {
+            let dst = match (path.strip_prefix("lab/"), path.strip_prefix("resources/")) {
+                (Some(path), _) => lab_path.join(path),
+                (_, Some(path)) => overlay_path.join(path),
+                _ => continue,
+            };
+
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&dst, content).map_err(|e| format!("could not write {:?}, {e}", dst))?;
+            event!(Level::DEBUG, "imported {:?}", dst);
+        }
+
+        let has_resources = files.keys().any(|p| p.starts_with("resources/"));
+        let runmd = lab_path.join(".runmd");
+        if has_resources {
+            let content = std::fs::read_to_string(&runmd).map_err(|e| e.to_string())?;
+            let overlay_path = overlay_path
+                .canonicalize()
+                .map_err(|e| format!("could not resolve {:?}, {e}", overlay_path))?;
+            let content = Self::with_overlay_dir(content, overlay_path.to_str().unwrap_or_default());
+            std::fs::write(&runmd, content).map_err(|e| e.to_string())?;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Sets `overlay_dir` in the project block of a .runmd, appending the block if the .runmd does not have one
+    ///
+    /// An existing `overlay_dir` points to where the lab was exported from, so it's replaced w/ where the lab was imported to
+    fn with_overlay_dir(runmd: impl AsRef<str>, overlay_dir: impl AsRef<str>) -> String {
+        let (block_name, symbol) = PROJECT_BLOCK;
+        let overlay_attr = format!("add overlay_dir .text {}", overlay_dir.as_ref());
+
+        let mut lines = vec![];
+        let mut in_block = false;
+        let mut found = false;
+        for line in runmd.as_ref().lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            if let Some(header) = line.trim().strip_prefix("```") {
+                in_block = header.split_whitespace().eq([block_name, symbol]);
+                lines.push(line.to_string());
+                if in_block {
+                    found = true;
+                    lines.push(overlay_attr.to_string());
+                }
+            } else if !(in_block && tokens.starts_with(&["add", "overlay_dir"])) {
+                lines.push(line.to_string());
+            }
+        }
+
+        if found {
+            format!("{}\n", lines.join("\n"))
+        } else {
+            format!("{}\n``` {block_name} {symbol}\n{overlay_attr}\n```\n", runmd.as_ref())
+        }
+    }
+
+    /// Returns the name of the lab, and the content of every file that should be exported by archive path
+    fn collect(
+        lab: impl AsRef<str>,
+        lab_dir: Option<String>,
+    ) -> Result<(String, BTreeMap<String, (Vec<u8>, Origin)>), String> {
+        let lab = lab.as_ref();
+        let mut files = BTreeMap::new();
+
+        let lab_path = PathBuf::from(lab);
+        let lab_folder = match (lab_path.is_file(), lab_dir) {
+            (true, _) => lab_path.parent().map(|p| p.to_path_buf()),
+            (false, Some(lab_dir)) if PathBuf::from(&lab_dir).join(lab).join(".runmd").is_file() => {
+                Some(PathBuf::from(lab_dir).join(lab))
+            }
+            _ => None,
+        };
+
+        let name = match &lab_folder {
+            Some(folder) => folder
+                .canonicalize()
+                .ok()
+                .and_then(|f| f.file_name().and_then(|n| n.to_str()).map(|n| n.to_string())),
+            None => Some(
+                lab.trim_start_matches("design/")
+                    .trim_end_matches(".runmd")
+                    .trim_end_matches('/')
+                    .to_string(),
+            ),
+        }
+        .filter(|n| !n.is_empty())
+        .ok_or(format!("could not find a name for {lab}"))?;
+
+        let resolver = Resolver::new();
+        match &lab_folder {
+            Some(folder) => {
+                for entry in walkdir::WalkDir::new(folder)
+                    .into_iter()
+                    .filter_entry(|e| e.file_name().to_str() != Some(OVERLAY_DIR_NAME))
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                {
+                    let rest = entry
+                        .path()
+                        .strip_prefix(folder)
+                        .ok()
+                        .and_then(|r| r.to_str())
+                        .map(|r| r.replace(std::path::MAIN_SEPARATOR, "/"));
+
+                    if let (Some(rest), Ok(content)) = (rest, std::fs::read(entry.path())) {
+                        files.insert(format!("lab/{rest}"), (content, Origin::Filesystem));
+                    }
+                }
+            }
+            None => {
+                let prefix = format!("design/{name}/");
+                for (path, _) in resolver.list(Some("design")) {
+                    if let Some(rest) = path.strip_prefix(&prefix) {
+                        if let Some(content) = resolver.get(&path) {
+                            files.insert(format!("lab/{rest}"), content);
+                        }
+                    }
+                }
+            }
+        }
+
+        let runmd = files
+            .get("lab/.runmd")
+            .and_then(|(content, _)| String::from_utf8(content.to_vec()).ok())
+            .ok_or(format!("could not find a .runmd for {lab}"))?;
+
+        let resolver = Project::load_content(runmd.clone())
+            .map(|p| Resolver::from_project(&p))
+            .unwrap_or(resolver);
+
+        for reference in Self::references(&runmd) {
+            if Path::new(&reference)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(format!(
+                    "cannot export {reference}, referenced files must be relative paths w/o `..`"
+                ));
+            }
+
+            let candidates = if reference.starts_with("lib/") || reference.starts_with("design/") {
+                vec![reference.clone()]
+            } else {
+                vec![format!("lib/cloud_init/{reference}"), reference.clone()]
+            };
+
+            match candidates
+                .iter()
+                .find_map(|c| resolver.get(c).map(|content| (c, content)))
+            {
+                Some((path, content)) => {
+                    files.insert(format!("resources/{path}"), content);
+                }
+                None => {
+                    event!(Level::WARN, "could not find {reference}, skipping");
+                    eprintln!("export: could not find {reference}, skipping");
+                }
+            }
+        }
+
+        Ok((name, files))
+    }
+
+    /// Returns every resource a .runmd references, from `file_src` attributes, `part` values, and any `lib/...` value
+    fn references(runmd: impl AsRef<str>) -> Vec<String> {
+        let mut references = vec![];
+
+        for line in runmd.as_ref().lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            let (name, symbol, value) = match tokens.as_slice() {
+                ["add", name, ".text", value @ ..] => (*name, None, value.join(" ")),
+                ["define", name, symbol, ".text", value @ ..] => (*name, Some(*symbol), value.join(" ")),
+                _ => continue,
+            };
+
+            if value.is_empty() || value.contains('{') || RemoteSource::parse(&value).is_some() {
+                continue;
+            }
+
+            let reference = match (name, symbol) {
+                // ex. define a_enter part .text enter-azure.yml_jinja2
+                (_, Some("part")) => value
+                    .rsplit_once('_')
+                    .map(|(file_name, _)| file_name.to_string())
+                    .unwrap_or(value),
+                ("file_src", _) => value,
+                _ if value.starts_with("lib/") => value,
+                _ => continue,
+            };
+
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+
+        references
+    }
+
+    /// Reads every file in an archive, verifying each one against the manifest
+    fn unpack(reader: impl Read) -> Result<(Manifest, BTreeMap<String, Vec<u8>>), String> {
+        let mut archive = tar::Archive::new(GzDecoder::new(reader));
+        let mut manifest = None;
+        let mut files = BTreeMap::new();
+
+        for entry in archive.entries().map_err(|e| e.to_string())? {
+            let mut entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path().map_err(|e| e.to_string())?.to_path_buf();
+
+            if path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                return Err(format!("archive contains an invalid path {:?}", path));
+            }
+
+            let path = path
+                .to_str()
+                .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"))
+                .ok_or(format!("archive contains an invalid path {:?}", path))?;
+
+            let mut content = vec![];
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| format!("could not read {path}, {e}"))?;
+
+            if path == MANIFEST_FILE_NAME {
+                manifest = Some(
+                    serde_json::from_slice::<Manifest>(&content)
+                        .map_err(|e| format!("could not parse manifest, {e}"))?,
+                );
+            } else {
+                files.insert(path, content);
+            }
+        }
+
+        let manifest = manifest.ok_or("archive does not contain a manifest".to_string())?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(format!("unsupported manifest version {}", manifest.version));
+        }
+
+        if manifest.name.is_empty() || manifest.name.contains(['/', '\\']) || manifest.name.starts_with('.') {
+            return Err(format!("invalid lab name {:?}", manifest.name));
+        }
+
+        for ManifestEntry { path, sha256, .. } in manifest.files.iter() {
+            match files.get(path) {
+                Some(content) if Install::sha256(content) == *sha256 => {}
+                Some(_) => return Err(format!("sha256 mismatch for {path}")),
+                None => return Err(format!("archive is missing {path}")),
+            }
+        }
+
+        if let Some(extra) = files
+            .keys()
+            .find(|p| !manifest.files.iter().any(|e| e.path == **p))
+        {
+            return Err(format!("{extra} is not in the manifest"));
+        }
+
+        if !files.contains_key("lab/.runmd") {
+            return Err("archive does not contain a lab/.runmd".to_string());
+        }
+
+        Ok((manifest, files))
+    }
+
+    /// Appends a file to the archive
+    fn append(
+        builder: &mut tar::Builder<GzEncoder<Vec<u8>>>,
+        path: impl AsRef<Path>,
+        content: &[u8],
+    ) -> Result<(), String> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        builder
+            .append_data(&mut header, path.as_ref(), content)
+            .map_err(|e| format!("could not add {:?} to archive, {e}", path.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Archive;
+
+    #[test]
+    fn test_with_overlay_dir() {
+        let runmd = "``` azure install\nadd file_src .text lib/sh/acr-login.sh\n```\n";
+        assert_eq!(
+            Archive::with_overlay_dir(runmd, "/labs/azure/overlay"),
+            format!("{runmd}\n``` chiron resources\nadd overlay_dir .text /labs/azure/overlay\n```\n")
+        );
+
+        let runmd = "``` chiron resources\nadd overlay_dir .text /home/exporter/azure/overlay\nadd lib_sh_overlay .text sh\n```\n\n``` azure install\nadd overlay_dir .text keep\n```\n";
+        assert_eq!(
+            Archive::with_overlay_dir(runmd, "/labs/azure/overlay"),
+            "``` chiron resources\nadd overlay_dir .text /labs/azure/overlay\nadd lib_sh_overlay .text sh\n```\n\n``` azure install\nadd overlay_dir .text keep\n```\n"
+        );
+    }
+}
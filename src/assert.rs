@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use lifec::plugins::{Plugin, ThunkContext};
+use lifec::{AttributeGraph, Component, DenseVecStorage, Value};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Evaluates the assertions defined in an `assert` block
+///
+/// Assertions are defined like expectations,
+///
+/// ````md
+/// ``` verify assert
+/// define token  file_exists     .text .run/acr_login/acr_token
+/// define log    file_contains   .text .run/acr_login/acr.log
+/// add    log_contains           .text Login Succeeded
+/// define login  stdout_matches  .text ^ey
+/// add    login_src              .text acr_login
+/// define portal http_status     .text http://localhost:3000/labs
+/// add    portal_status          .text 200
+/// ```
+/// ````
+///
+/// `stdout_matches` reads `stdout` from the block named by `{name}_src`, or from the previous event if not set.
+/// The plugin only receives the previous event, so the runner passes the stdout of each `{name}_src` block along as `{src}_stdout`, see `with_sources`.
+/// Relative paths are read from `work_dir`, if set.
+///
+#[derive(Component, Default)]
+#[storage(DenseVecStorage)]
+pub struct Assert;
+
+/// Result of evaluating a single assertion
+///
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Assertion {
+    /// Name of the block the assertion belongs to
+    pub block_name: String,
+    /// Name of the assertion, ex. `token`
+    pub name: String,
+    /// Kind of assertion, ex. `file_exists`
+    pub kind: String,
+    /// Error message if the assertion failed, `None` if the assertion passed
+    pub error: Option<String>,
+}
+
+impl Assertion {
+    /// Returns true if this assertion passed
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Plugin<ThunkContext> for Assert {
+    fn symbol() -> &'static str {
+        "assert"
+    }
+
+    fn description() -> &'static str {
+        "Evaluates file_exists, file_contains, stdout_matches and http_status assertions."
+    }
+
+    fn call_with_context(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
+        context.clone().task(|_| {
+            let mut tc = context.clone();
+            async move {
+                let block_name = tc.block.block_name.to_string();
+                let assertions = Assert::evaluate(&block_name, tc.as_ref(), &BTreeMap::default()).await;
+
+                for assertion in assertions.iter() {
+                    let Assertion { name, kind, error, .. } = assertion;
+                    match error {
+                        Some(error) => {
+                            tc.update_status_only(format!("{name} {kind} failed, {error}")).await;
+                        }
+                        None => {
+                            tc.update_status_only(format!("{name} {kind} passed")).await;
+                        }
+                    }
+                }
+
+                let failed = assertions.iter().filter(|a| !a.passed()).count();
+                if let Ok(results) = serde_json::to_vec(&assertions) {
+                    tc.as_mut().add_binary_attr("assertions", results);
+                }
+                tc.as_mut().add_bool_attr("assert_failed", failed > 0);
+
+                Some(tc)
+            }
+        })
+    }
+}
+
+impl Assert {
+    /// Evaluates every assertion defined in `graph`,
+    ///
+    /// `outputs` are the results of previous events by block name, used to look up `{name}_src`
+    pub async fn evaluate(
+        block_name: impl AsRef<str>,
+        graph: &AttributeGraph,
+        outputs: &BTreeMap<String, AttributeGraph>,
+    ) -> Vec<Assertion> {
+        let mut assertions = vec![];
+        let work_dir = graph.find_text("work_dir").map(PathBuf::from).unwrap_or_default();
+
+        for kind in ["file_exists", "file_contains", "stdout_matches", "http_status"] {
+            for (name, value) in graph.clone().find_symbol_values(kind) {
+                let suffix = format!("::{kind}");
+                if let (Some(name), Value::TextBuffer(value)) = (name.strip_suffix(&suffix), value) {
+                    let error = match kind {
+                        "file_exists" => Self::file_exists(work_dir.join(&value)).await,
+                        "file_contains" => {
+                            let expected = graph.find_text(format!("{name}_contains")).unwrap_or_default();
+                            Self::file_contains(work_dir.join(&value), expected).await
+                        }
+                        "stdout_matches" => {
+                            let stdout = match graph.find_text(format!("{name}_src")) {
+                                Some(src) => outputs
+                                    .get(&src)
+                                    .and_then(|o| o.find_binary("stdout"))
+                                    .or(graph.find_binary(format!("{src}_stdout"))),
+                                None => graph.find_binary("stdout"),
+                            };
+                            Self::stdout_matches(&value, stdout)
+                        }
+                        "http_status" => {
+                            let expected = graph
+                                .find_text(format!("{name}_status"))
+                                .unwrap_or("200".to_string());
+                            Self::http_status(&value, expected).await
+                        }
+                        _ => None,
+                    };
+
+                    assertions.push(Assertion {
+                        block_name: block_name.as_ref().to_string(),
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        error,
+                    });
+                }
+            }
+        }
+
+        assertions
+    }
+
+    /// Adds the stdout of each block named by a `{name}_src` attribute in `graph` as `{src}_stdout`, so the plugin can evaluate it
+    pub fn with_sources(graph: &mut AttributeGraph, outputs: &BTreeMap<String, AttributeGraph>) {
+        for (name, _) in graph.clone().find_symbol_values("stdout_matches") {
+            let src = name
+                .strip_suffix("::stdout_matches")
+                .and_then(|name| graph.find_text(format!("{name}_src")));
+
+            if let Some(src) = src {
+                if let Some(stdout) = outputs.get(&src).and_then(|o| o.find_binary("stdout")) {
+                    graph.add_binary_attr(format!("{src}_stdout"), stdout);
+                }
+            }
+        }
+    }
+
+    async fn file_exists(path: PathBuf) -> Option<String> {
+        if path.exists() {
+            None
+        } else {
+            Some(format!("{} does not exist", path.display()))
+        }
+    }
+
+    async fn file_contains(path: PathBuf, expected: impl AsRef<str>) -> Option<String> {
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) if content.contains(expected.as_ref()) => None,
+            Ok(_) => Some(format!("{} does not contain `{}`", path.display(), expected.as_ref())),
+            Err(err) => Some(format!("could not read {}, {err}", path.display())),
+        }
+    }
+
+    fn stdout_matches(pattern: impl AsRef<str>, stdout: Option<Vec<u8>>) -> Option<String> {
+        match (Regex::new(pattern.as_ref()), stdout) {
+            (Ok(regex), Some(stdout)) => {
+                if regex.is_match(&String::from_utf8_lossy(&stdout)) {
+                    None
+                } else {
+                    Some(format!("stdout does not match `{}`", pattern.as_ref()))
+                }
+            }
+            (Ok(_), None) => Some("no stdout was found".to_string()),
+            (Err(err), _) => Some(format!("invalid pattern, {err}")),
+        }
+    }
+
+    async fn http_status(url: impl AsRef<str>, expected: impl AsRef<str>) -> Option<String> {
+        match reqwest::get(url.as_ref()).await {
+            Ok(response) if response.status().as_str() == expected.as_ref() => None,
+            Ok(response) => Some(format!(
+                "{} returned {}, expected {}",
+                url.as_ref(),
+                response.status().as_str(),
+                expected.as_ref()
+            )),
+            Err(err) => Some(format!("could not reach {}, {err}", url.as_ref())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use lifec::plugins::{Plugin, Project, ThunkContext};
+    use lifec::AttributeGraph;
+
+    use super::Assert;
+
+    /// Returns the `assert` block of a project w/ a `stdout_matches` assertion on `acr_login`
+    fn assert_block() -> AttributeGraph {
+        let project = Project::load_content(
+            r#"
+``` verify assert
+define login  stdout_matches  .text ^ey
+add    login_src              .text acr_login
+```
+"#
+            .to_string(),
+        )
+        .expect("should load project");
+
+        project
+            .find_block("verify")
+            .and_then(|b| b.get_block("assert"))
+            .expect("should have an assert block")
+    }
+
+    /// Returns outputs where `acr_login` wrote a token to stdout
+    fn outputs() -> BTreeMap<String, AttributeGraph> {
+        let mut acr_login = AttributeGraph::from(0);
+        acr_login.add_binary_attr("stdout", b"eyJhbGciOiJSUzI1NiJ9".to_vec());
+        BTreeMap::from([("acr_login".to_string(), acr_login)])
+    }
+
+    #[tokio::test]
+    async fn test_stdout_matches() {
+        let graph = assert_block();
+
+        let assertions = Assert::evaluate("verify", &graph, &outputs()).await;
+        assert_eq!(assertions.len(), 1);
+        assert!(assertions[0].passed(), "{:?}", assertions[0].error);
+
+        let assertions = Assert::evaluate("verify", &graph, &BTreeMap::default()).await;
+        assert!(!assertions[0].passed());
+    }
+
+    #[tokio::test]
+    async fn test_plugin_stdout_matches() {
+        let mut tc = ThunkContext::default();
+        tc.block.block_name = "verify".to_string();
+        tc.handle = Some(tokio::runtime::Handle::current());
+        *tc.as_mut() = assert_block();
+        Assert::with_sources(tc.as_mut(), &outputs());
+
+        let (task, _cancel) = Assert::call_with_context(&mut tc).expect("should start");
+        let result = task.await.expect("should join").expect("should return a result");
+        assert_eq!(result.as_ref().is_enabled("assert_failed"), Some(false));
+    }
+}
@@ -1,15 +1,495 @@
-use lifec::plugins::{ThunkContext, Plugin, Println, combine, Expect};
+use futures_util::future::join_all;
+use lifec::plugins::{Expect, Plugin, Project, ThunkContext};
+use lifec::{AttributeGraph, Component, DenseVecStorage, Value};
+use phf::phf_map;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
 
+use crate::design::Design;
+use crate::install::Install;
+use crate::resources::Resolver;
 
-#[derive(Default)]
+/// Default work_dir fix scripts are installed to
+pub const FIX_WORK_DIR: &str = ".run/fix";
+
+/// Attributes passed along to the `install` plugin when installing a fix script, in addition to `{prefix}_overlay`
+const FIX_INSTALL_ATTRIBUTES: [&str; 5] = ["dry_run", "cache_dir", "allow_unpinned", "overlay_dir", "backup"];
+
+/// Environment doctor, gathers every `expect` block from a project and the embedded labs,
+/// and checks each expectation in parallel
+///
+#[derive(Component, Default)]
+#[storage(DenseVecStorage)]
 pub struct Check;
 
+/// Result of checking a single dependency from an `expect` block
+///
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Expectation {
+    /// Name of the project or lab the expect block was found in
+    pub source: String,
+    /// Name of the block the expect block belongs to
+    pub block_name: String,
+    /// The dependency being checked, ex. `az`
+    pub dep: String,
+    /// Hint to display if the check fails
+    pub remediation: String,
+    /// Script that can be installed and run to fix a failed check, ex. `lib/sh/fix-azcli.sh`
+    pub fix_script: Option<String>,
+    /// Error message if the check failed, `None` if the check passed
+    pub error: Option<String>,
+}
+
+impl Expectation {
+    /// Returns true if this expectation was met
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
 impl Plugin<ThunkContext> for Check {
     fn symbol() -> &'static str {
         "check"
     }
 
+    fn description() -> &'static str {
+        "Checks every `expect` block in {project_src}, {lab_dir}, and the embedded labs, and prints a report."
+    }
+
     fn call_with_context(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
-        combine::<Expect, Println>()(context)
+        context.clone().task(|_| {
+            let mut tc = context.clone();
+            async move {
+                let project_src = tc.as_ref().find_text("project_src");
+                let lab_dir = tc.as_ref().find_text("lab_dir");
+                let skip_builtin = tc.as_ref().is_enabled("skip_builtin").unwrap_or_default();
+                let work_dir = tc.as_ref().find_text("work_dir").unwrap_or(FIX_WORK_DIR.to_string());
+                let fix_config = Check::fix_config(tc.as_ref(), work_dir);
+
+                tc.update_status_only("checking expectations").await;
+                let sources = Check::find_sources(project_src, lab_dir, skip_builtin).await;
+                let mut expectations = Check::run(Check::gather(sources)).await;
+
+                if tc.as_ref().is_enabled("enable_fix").unwrap_or_default() {
+                    tc.update_status_only("running fix scripts").await;
+                    expectations = Check::fix_all(expectations, &fix_config).await;
+                }
+
+                let report = Check::report(&expectations);
+                println!("{report}");
+
+                let failed = expectations.iter().filter(|e| !e.passed()).count();
+                tc.update_status_only(format!(
+                    "{} expectations checked, {failed} failed",
+                    expectations.len()
+                ))
+                .await;
+
+                tc.as_mut()
+                    .with_text("report", report)
+                    .add_bool_attr("check_failed", failed > 0);
+
+                Some(tc)
+            }
+        })
+    }
+}
+
+impl Check {
+    /// Runs all checks and prints the report, returns true if every expectation passed
+    pub async fn doctor(
+        project_src: Option<String>,
+        lab_dir: Option<String>,
+        skip_builtin: bool,
+        fix: bool,
+    ) -> bool {
+        let sources = Check::find_sources(project_src, lab_dir, skip_builtin).await;
+        let mut expectations = Check::run(Check::gather(sources)).await;
+
+        println!("{}", Check::report(&expectations));
+
+        if fix && expectations.iter().any(|e| !e.passed() && e.fix_script.is_some()) {
+            println!("\nRunning fix scripts\n");
+            let fix_config = Check::fix_config(&AttributeGraph::from(0), FIX_WORK_DIR);
+            expectations = Check::fix_all(expectations, &fix_config).await;
+            println!("{}", Check::report(&expectations));
+        }
+
+        expectations.iter().all(Expectation::passed)
+    }
+
+    /// Returns the (source name, .runmd content) of every project that should be checked
+    pub async fn find_sources(
+        project_src: Option<String>,
+        lab_dir: Option<String>,
+        skip_builtin: bool,
+    ) -> Vec<(String, String)> {
+        let mut sources = vec![];
+
+        let project_src = project_src.unwrap_or(".runmd".to_string());
+        match tokio::fs::read_to_string(&project_src).await {
+            Ok(content) => sources.push((project_src, content)),
+            Err(err) => {
+                event!(Level::DEBUG, "skipping project {project_src}, {err}");
+            }
+        }
+
+        let resolver = Resolver::new();
+        for lab in Design::discover(lab_dir.as_deref()) {
+            if skip_builtin && lab.embedded {
+                continue;
+            }
+
+            if let Some(err) = lab.error {
+                eprintln!("skipping lab {} at {}, {err}", lab.name, lab.location);
+                continue;
+            }
+
+            match resolver.get_string(&lab.location) {
+                Some(content) => sources.push((lab.location, content)),
+                None => {
+                    event!(Level::ERROR, "could not read lab {}", lab.location);
+                }
+            }
+        }
+
+        sources
+    }
+
+    /// Gathers every `which` expectation from the `expect` blocks in each source
+    pub fn gather(sources: Vec<(String, String)>) -> Vec<Expectation> {
+        let mut expectations = vec![];
+
+        for (source, content) in sources {
+            if let Some(project) = Project::load_content(content) {
+                for (block_name, block) in project.iter_block() {
+                    if let Some(expect) = block.get_block("expect") {
+                        let remediation = expect.find_text("remediation");
+                        let fix_script = expect
+                            .find_text(format!("fix_script_{}", std::env::consts::OS))
+                            .or(expect.find_text("fix_script"));
+
+                        for (name, value) in expect.clone().find_symbol_values("which") {
+                            if Expect::should_expect(name, "which") {
+                                if let Value::TextBuffer(dep) = value {
+                                    expectations.push(Expectation {
+                                        source: source.to_string(),
+                                        block_name: block_name.to_string(),
+                                        remediation: remediation
+                                            .clone()
+                                            .unwrap_or(Check::remediation_hint(&dep)),
+                                        fix_script: fix_script
+                                            .clone()
+                                            .or(Check::default_fix_script(&dep)),
+                                        dep,
+                                        error: None,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+            } else {
+                event!(Level::ERROR, "could not parse {source}");
+            }
+        }
+
+        expectations
+    }
+
+    /// Checks each expectation in parallel
+    ///
+    /// A check whose task panics or is cancelled fails, instead of being dropped from the results
+    pub async fn run(expectations: Vec<Expectation>) -> Vec<Expectation> {
+        let checks = expectations.iter().cloned().map(|mut expectation| {
+            tokio::spawn(async move {
+                if !Check::which(&expectation.dep).await {
+                    expectation.error = Some(format!("`{}` was not found", expectation.dep));
+                }
+                expectation
+            })
+        });
+
+        join_all(checks)
+            .await
+            .into_iter()
+            .zip(expectations)
+            .map(|(result, mut expectation)| match result {
+                Ok(checked) => checked,
+                Err(err) => {
+                    expectation.error = Some(format!("could not check `{}`, {err}", expectation.dep));
+                    expectation
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the fix script for each failed expectation that has one, and then checks the expectation again
+    pub async fn fix_all(expectations: Vec<Expectation>, fix_config: &AttributeGraph) -> Vec<Expectation> {
+        let mut fixed = vec![];
+        // Fix scripts install packages, so these are run one at a time
+        for expectation in expectations {
+            if expectation.passed() || expectation.fix_script.is_none() {
+                fixed.push(expectation);
+            } else {
+                fixed.push(Check::fix(expectation, fix_config).await);
+            }
+        }
+        fixed
+    }
+
+    /// Returns the config fix scripts are installed w/, `work_dir` and the install attributes of `graph`
+    ///
+    /// Fix scripts are installed by the `install` plugin, so `dry_run`, overlays and the work_dir's ledger apply.
+    /// A fix script can be pinned w/ `{dep}_sha256`, ex. `az_sha256`
+    pub fn fix_config(graph: &AttributeGraph, work_dir: impl AsRef<str>) -> AttributeGraph {
+        let mut fix_config = AttributeGraph::from(0);
+        fix_config.add_text_attr("work_dir", work_dir.as_ref());
+
+        for attribute in graph.iter_attributes() {
+            let name = attribute.name();
+            if !FIX_INSTALL_ATTRIBUTES.contains(&name) && !name.ends_with("_overlay") && !name.ends_with("_sha256") {
+                continue;
+            }
+
+            match attribute.value() {
+                Value::TextBuffer(text) => {
+                    fix_config.add_text_attr(name, text);
+                }
+                Value::Bool(enabled) => {
+                    fix_config.add_bool_attr(name, *enabled);
+                }
+                _ => {}
+            }
+        }
+
+        fix_config
+    }
+
+    /// Installs and runs the fix script for an expectation, and then checks the expectation again
+    ///
+    /// If `dry_run` is enabled in `fix_config`, the script is not run, and the expectation is returned as is
+    pub async fn fix(mut expectation: Expectation, fix_config: &AttributeGraph) -> Expectation {
+        if let Some(fix_script) = expectation.fix_script.clone() {
+            event!(Level::INFO, "running {fix_script} to fix {}", expectation.dep);
+
+            let mut install_config = fix_config.clone();
+            if let Some(sha256) = fix_config.find_text(format!("{}_sha256", expectation.dep)) {
+                install_config.add_text_attr("sha256", sha256);
+            }
+
+            match Install::install_with("fix", &install_config, &fix_script).await {
+                Ok(_) if fix_config.is_enabled("dry_run").unwrap_or_default() => {
+                    eprintln!("fix (dry_run): would run {fix_script} to fix {}", expectation.dep);
+                }
+                Ok(script) => {
+                    let content = tokio::fs::read_to_string(&script).await.unwrap_or_default();
+                    let mut interpreter = content
+                        .lines()
+                        .next()
+                        .and_then(|l| l.strip_prefix("#!"))
+                        .unwrap_or("sh")
+                        .split_whitespace();
+
+                    let mut command = tokio::process::Command::new(interpreter.next().unwrap_or("sh"));
+                    command.args(interpreter).arg(&script);
+
+                    match command.status().await {
+                        Ok(status) if status.success() => {
+                            expectation.error = None;
+                            if !Check::which(&expectation.dep).await {
+                                expectation.error = Some(format!(
+                                    "`{}` was still not found after running {fix_script}",
+                                    expectation.dep
+                                ));
+                            }
+                        }
+                        Ok(status) => {
+                            expectation.error = Some(format!("{fix_script} exited with {status}"));
+                        }
+                        Err(err) => {
+                            expectation.error = Some(format!("could not run {fix_script}, {err}"));
+                        }
+                    }
+                }
+                Err(err) => {
+                    expectation.error = Some(format!("could not install {fix_script}, {err}"));
+                }
+            }
+        }
+
+        expectation
+    }
+
+    /// Formats expectations into a table
+    pub fn report(expectations: &[Expectation]) -> String {
+        let header = ["STATUS", "SOURCE", "BLOCK", "DEPENDENCY", "REMEDIATION"];
+        let rows = expectations
+            .iter()
+            .map(|e| {
+                [
+                    if e.passed() { "pass" } else { "fail" }.to_string(),
+                    e.source.to_string(),
+                    e.block_name.to_string(),
+                    e.dep.to_string(),
+                    match e {
+                        Expectation { error: None, .. } => "-".to_string(),
+                        Expectation {
+                            fix_script: Some(fix_script),
+                            remediation,
+                            ..
+                        } => format!("{remediation} (fix: {fix_script})"),
+                        Expectation { remediation, .. } => remediation.to_string(),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut widths = header.map(|h| h.len());
+        for row in rows.iter() {
+            for (width, column) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(column.len());
+            }
+        }
+
+        let format_row = |row: &[String]| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(c, w)| format!("{c:<w$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        let mut lines = vec![format_row(&header.map(|h| h.to_string()))];
+        for row in rows.iter() {
+            lines.push(format_row(row));
+        }
+
+        let failed = expectations.iter().filter(|e| !e.passed()).count();
+        lines.push(String::default());
+        lines.push(format!(
+            "{} passed, {failed} failed",
+            expectations.len() - failed
+        ));
+
+        lines.join("\n")
+    }
+
+    /// Returns the default remediation hint for a dependency
+    fn remediation_hint(dep: impl AsRef<str>) -> String {
+        REMEDIATION_HINTS
+            .get(dep.as_ref())
+            .map(|h| h.to_string())
+            .unwrap_or(format!("install `{}` and make sure it is in PATH", dep.as_ref()))
+    }
+
+    /// Returns the default fix script for a dependency on the current os
+    fn default_fix_script(dep: impl AsRef<str>) -> Option<String> {
+        FIX_SCRIPTS
+            .get(format!("{}.{}", dep.as_ref(), std::env::consts::OS).as_str())
+            .map(|s| s.to_string())
+    }
+
+    /// Returns true if the program can be found, w/ the same `which` lookup the `expect` plugin runs
+    async fn which(program: impl AsRef<str>) -> bool {
+        let program = program.as_ref();
+
+        let mut tc = ThunkContext::default();
+        tc.handle = Some(tokio::runtime::Handle::current());
+        tc.as_mut().add_text_attr(format!("{program}::which"), program);
+
+        match Expect::call_with_context(&mut tc) {
+            Some((task, _cancel)) => matches!(task.await, Ok(Some(result)) if result.get_errors().is_none()),
+            None => false,
+        }
+    }
+}
+
+/// Default hints for dependencies the embedded labs expect
+const REMEDIATION_HINTS: phf::Map<&'static str, &'static str> = phf_map! {
+    "az" => "install the azure cli, https://aka.ms/InstallAzureCLIDeb",
+    "jq" => "install jq with your package manager",
+    "docker" => "install docker, https://docs.docker.com/engine/install/",
+    "python3" => "install python3 with your package manager",
+    "pip" => "install pip with your package manager",
+};
+
+/// Default fix scripts, keyed by `{dep}.{os}`
+///
+/// Expect blocks can override these with `fix_script_{os}` or `fix_script` text attributes
+const FIX_SCRIPTS: phf::Map<&'static str, &'static str> = phf_map! {
+    "az.linux" => "lib/sh/fix-azcli.sh",
+    "az.macos" => "lib/sh/fix-azcli-macos.sh",
+    "jq.linux" => "lib/sh/fix-jq.sh",
+    "jq.macos" => "lib/sh/fix-jq-macos.sh",
+    "pip.linux" => "lib/sh/fix-pip-azure-cli.sh",
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{Check, Expectation};
+
+    const PROJECT: &str = r#"
+``` test expect
+define sh       which   .text sh
+define missing  which   .text chiron-test-missing-dep
+add remediation         .text install the missing dep
+```
+"#;
+
+    /// Returns the path to a project file w/ `content`, written for a test
+    fn project_src(test: &str, content: &str) -> String {
+        let project_src = std::env::temp_dir().join(format!("chiron-check-{}-{test}.runmd", std::process::id()));
+        std::fs::write(&project_src, content).expect("should write project");
+        project_src.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_gather() {
+        let expectations = Check::gather(vec![("project".to_string(), PROJECT.to_string())]);
+
+        let deps = expectations.iter().map(|e| e.dep.as_str()).collect::<Vec<_>>();
+        assert_eq!(deps, vec!["sh", "chiron-test-missing-dep"]);
+        assert!(expectations.iter().all(|e| e.source == "project" && e.block_name == "test"));
+        assert!(expectations.iter().all(|e| e.remediation == "install the missing dep"));
+        assert!(expectations.iter().all(Expectation::passed));
+    }
+
+    #[test]
+    fn test_report() {
+        let expectations = vec![
+            Expectation {
+                source: "project".to_string(),
+                block_name: "test".to_string(),
+                dep: "sh".to_string(),
+                ..Default::default()
+            },
+            Expectation {
+                source: "project".to_string(),
+                block_name: "test".to_string(),
+                dep: "az".to_string(),
+                remediation: "install az".to_string(),
+                fix_script: Some("lib/sh/fix-azcli.sh".to_string()),
+                error: Some("`az` was not found".to_string()),
+            },
+        ];
+
+        let report = Check::report(&expectations);
+        let lines = report.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("STATUS"));
+        assert!(lines[1].starts_with("pass") && lines[1].ends_with("-"));
+        assert!(lines[2].starts_with("fail") && lines[2].ends_with("install az (fix: lib/sh/fix-azcli.sh)"));
+        assert_eq!(lines.last(), Some(&"1 passed, 1 failed"));
+    }
+
+    #[tokio::test]
+    async fn test_doctor() {
+        let missing = project_src("missing", PROJECT);
+        assert!(!Check::doctor(Some(missing), None, true, false).await);
+
+        let found = project_src("found", "``` test expect\ndefine sh which .text sh\n```\n");
+        assert!(Check::doctor(Some(found), None, true, false).await);
     }
-}
\ No newline at end of file
+}
@@ -21,6 +21,10 @@ impl Plugin<ThunkContext> for Installer {
     }
 
     fn call_with_context(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
+        if context.as_ref().is_enabled("dry_run").unwrap_or_default() {
+            return Self::dry_run(context);
+        }
+
         context.clone().task(|_| {
             let tc = context.clone();
             async move {
@@ -85,3 +89,61 @@ impl Plugin<ThunkContext> for Installer {
         })
     }
 }
+
+impl Installer {
+    /// Prints the packages, files, and commands of each part, w/o installing anything
+    ///
+    /// Fails if a part cannot be read or parsed
+    fn dry_run(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
+        context.clone().task(|_| {
+            let tc = context.clone();
+            async move {
+                for part in find_parts(&tc).await {
+                    let content = match tokio::fs::read_to_string(&part).await {
+                        Ok(content) => content,
+                        Err(err) => {
+                            eprintln!("installer (dry_run): could not read {part}, {err}");
+                            return None;
+                        }
+                    };
+
+                    let yaml = match serde_yaml::from_str::<serde_yaml::Value>(&content) {
+                        Ok(yaml) => yaml,
+                        Err(err) => {
+                            eprintln!("installer (dry_run): could not parse {part}, {err}");
+                            return None;
+                        }
+                    };
+
+                    let strings = |key: &str| {
+                        yaml.get(key)
+                            .and_then(|v| v.as_sequence())
+                            .map(|s| s.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>())
+                            .unwrap_or_default()
+                    };
+
+                    eprintln!("installer (dry_run): {part}");
+                    for package in strings("packages") {
+                        eprintln!("  would install package {package}");
+                    }
+
+                    for path in yaml
+                        .get("write_files")
+                        .and_then(|v| v.as_sequence())
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|w| w.get("path").and_then(|p| p.as_str()))
+                    {
+                        eprintln!("  would write {path}");
+                    }
+
+                    for cmd in strings("runcmd") {
+                        eprintln!("  would run {cmd}");
+                    }
+                }
+
+                Some(tc)
+            }
+        })
+    }
+}
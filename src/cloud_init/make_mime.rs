@@ -12,7 +12,7 @@ use tracing::{event, Level};
 use std::{collections::hash_map::DefaultHasher, hash::Hasher, path::PathBuf};
 use tokio::io::{self, AsyncWriteExt};
 
-use crate::cloud_init::UserData;
+use crate::resources::Resolver;
 
 use super::find_parts;
 
@@ -35,6 +35,11 @@ impl Plugin<ThunkContext> for MakeMime {
             async move {
                 if let Some(work_dir) = tc.as_ref().find_text("work_dir") {
                     if let Some(file_dst) = tc.as_ref().find_text("file_dst") {
+                        if tc.as_ref().is_enabled("dry_run").unwrap_or_default() {
+                            Self::dry_run(Resolver::from(tc.as_ref()), find_parts(&tc).await, work_dir, file_dst).await;
+                            return Some(tc);
+                        }
+
                         tc.update_status_only(format!("writing user_data to {file_dst}"))
                             .await;
                         tokio::fs::create_dir_all(
@@ -52,7 +57,8 @@ impl Plugin<ThunkContext> for MakeMime {
                             .await
                         {
                             Ok(file) => {
-                                match Self::make_mime(find_parts(&tc).await, work_dir, file).await {
+                                let resolver = Resolver::from(tc.as_ref());
+                                match Self::make_mime(&resolver, find_parts(&tc).await, work_dir, file).await {
                                     Ok(_) => {
                                         event!(Level::TRACE, "created cloud_init mime package");
                                     }
@@ -85,7 +91,28 @@ impl Plugin<ThunkContext> for MakeMime {
 }
 
 impl MakeMime {
+    /// Prints the parts that would be included in the user_data written to `file_dst`
+    async fn dry_run(resolver: Resolver, parts: Vec<String>, work_dir: impl AsRef<str>, file_dst: impl AsRef<str>) {
+        eprintln!("make_mime (dry_run): would write user_data to {}", file_dst.as_ref());
+
+        for node in parts {
+            if let Some((file_name, mime_type)) = node.split_once("_") {
+                let found = Self::get_userdata_content(&resolver, work_dir.as_ref(), file_name)
+                    .await
+                    .is_some();
+
+                match (found, CLOUD_INIT_MIME_TYPES.get(mime_type)) {
+                    (true, Some(mime_type)) => eprintln!("  would add part {file_name} ({mime_type})"),
+                    (false, _) => eprintln!("  would skip part {file_name}, file not found"),
+                    (_, None) => eprintln!("  would skip part {file_name}, unknown mime type {mime_type}"),
+                }
+            }
+        }
+    }
+
+    /// Resolves the content of a part, parts under `lib/cloud_init` fall back to the embedded folder
     async fn get_userdata_content(
+        resolver: &Resolver,
         work_dir: impl AsRef<str>,
         file_name: impl AsRef<str>,
     ) -> Option<String> {
@@ -93,16 +120,11 @@ impl MakeMime {
 
         eprintln!("Trying to find part from path {:?}", file_path);
 
-        if !file_path.exists() && file_path.starts_with("lib/cloud_init") {
-            if let Some(content) = UserData::get(file_name.as_ref()) {
-                return String::from_utf8(content.data.to_vec()).ok();
-            }
-        }
-
-        tokio::fs::read_to_string(&file_path).await.ok()
+        file_path.to_str().and_then(|path| resolver.get_string(path))
     }
 
     async fn make_mime(
+        resolver: &Resolver,
         parts: Vec<String>,
         work_dir: impl AsRef<str>,
         mut file: tokio::fs::File,
@@ -114,7 +136,7 @@ impl MakeMime {
             if let Some((file_name, mime_type)) = node.split_once("_") {
                 let file_path = PathBuf::from(work_dir.as_ref()).join(file_name);
 
-                match Self::get_userdata_content(work_dir.as_ref(), file_name).await {
+                match Self::get_userdata_content(resolver, work_dir.as_ref(), file_name).await {
                     Some(body) => match CLOUD_INIT_MIME_TYPES[mime_type].parse::<Mime>() {
                         Ok(mime_type) => {
                             let file_name = file_path
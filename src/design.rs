@@ -1,4 +1,8 @@
+use std::{collections::BTreeMap, path::Path};
+
+use lifec::plugins::Project;
 use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
 use tracing::event;
 use tracing::Level;
 
@@ -6,6 +10,49 @@ use tracing::Level;
 #[folder = "design"]
 pub struct Design;
 
+/// Default number of directories below `lab_dir` that are searched for labs
+pub const DEFAULT_MAX_DEPTH: usize = 4;
+
+/// Name of the file in `lab_dir` w/ additional directories to skip, one per line
+const IGNORE_FILE_NAME: &str = ".labignore";
+
+/// Directories that are never searched for labs
+const IGNORED_DIRS: [&str; 4] = ["target", "node_modules", "elm-stuff", "overlay"];
+
+/// Optional metadata for a lab, read from a `lab.toml` next to the lab's `.runmd`, or from front matter at the top of the `.runmd`, ex.
+///
+/// ```md
+/// ---
+/// title: Azure development
+/// version: 0.1.0
+/// ---
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LabManifest {
+    pub title: Option<String>,
+    pub overview: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A lab found by discovery
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LabEntry {
+    /// Name of the lab, the path of the lab's folder relative to where it was found, ex. `azure` or `team/azure`
+    pub name: String,
+    /// Path of the lab's `.runmd` relative to where it was found, always `{name}/.runmd`
+    pub path: String,
+    /// Where the `.runmd` can be read from, a path on disk, or `design/{name}/.runmd` for embedded labs
+    pub location: String,
+    /// True if the lab is embedded in chiron
+    pub embedded: bool,
+    /// Metadata from the lab's manifest, if any
+    pub manifest: LabManifest,
+    /// Set if the lab's `.runmd` or manifest could not be parsed
+    pub error: Option<String>,
+}
 
 impl Design {
     pub fn labs() -> Vec<String> {
@@ -15,40 +62,150 @@ impl Design {
             .collect()
     }
 
-    pub async fn find_labs(dir: impl AsRef<str>) -> Vec<String> {
+    /// Returns the embedded labs, and the labs in `lab_dir`,
+    ///
+    /// Labs in `lab_dir` take precedence over embedded labs w/ the same name. Labs that fail to parse are included w/ an error
+    pub fn discover(lab_dir: Option<&str>) -> Vec<LabEntry> {
+        let mut labs = BTreeMap::new();
+
+        for path in Self::labs() {
+            let name = path.trim_end_matches("/.runmd").to_string();
+            let content = Design::get(&path)
+                .map(|f| String::from_utf8_lossy(f.data.as_ref()).to_string())
+                .unwrap_or_default();
+
+            let mut lab = Self::parse(&content, None);
+            lab.location = format!("design/{path}");
+            lab.path = path;
+            lab.embedded = true;
+            labs.insert(name.to_string(), LabEntry { name, ..lab });
+        }
+
+        if let Some(lab_dir) = lab_dir {
+            for lab in Self::find_labs(lab_dir, DEFAULT_MAX_DEPTH) {
+                if labs.contains_key(&lab.name) {
+                    event!(Level::DEBUG, "{} in {lab_dir} replaces the embedded lab", lab.name);
+                }
+                labs.insert(lab.name.to_string(), lab);
+            }
+        }
+
+        labs.into_values().collect()
+    }
+
+    /// Searches `dir` for labs, up to `max_depth` directories deep
+    ///
+    /// Hidden directories, build output, and directories listed in `{dir}/.labignore` are skipped
+    pub fn find_labs(dir: impl AsRef<str>, max_depth: usize) -> Vec<LabEntry> {
+        let dir = Path::new(dir.as_ref());
+        let ignore = Self::ignore_rules(dir);
         let mut labs = vec![];
 
-        event!(Level::DEBUG, "searching for labs in {}", dir.as_ref());
-        if let Some(mut read_dir) = tokio::fs::read_dir(dir.as_ref()).await.ok() {
-            while let Ok(Some(entry))  = read_dir.next_entry().await  {
-                event!(Level::TRACE, "enumerated {:?}", entry);
-
-                match entry.file_type().await {
-                    Ok(file_type) => {
-                        if file_type.is_dir() {
-                            if let Some(mut read_dir) = tokio::fs::read_dir(entry.path()).await.ok() {
-                                while let Ok(Some(inner_entry)) = read_dir.next_entry().await {
-                                    event!(Level::TRACE, "enumerated {:?}", inner_entry);
-                                    if let Some(_) = inner_entry.path().to_str().filter(|s| s.ends_with("/.runmd")) {
-                                        let path = entry.file_name().to_str().unwrap_or_default().to_string();
-                                        labs.push(format!("{path}/.runmd"));
-                                    }
-                                }
-                            }
-                        } else {   
-                            if let Some(entry) = entry.path().to_str().filter(|s| s.ends_with("/.runmd")) {
-                                labs.push(entry.to_string());
-                            }
-                        }
-                    },
-                    Err(err) => {
-                        event!(Level::ERROR, "error searching dir {err}");
-                    },
+        event!(Level::DEBUG, "searching for labs in {:?}", dir);
+        for entry in walkdir::WalkDir::new(dir)
+            .max_depth(max_depth + 1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| {
+                e.depth() == 0
+                    || !e.file_type().is_dir()
+                    || !Self::is_ignored(e.path().strip_prefix(dir).unwrap_or(e.path()), &ignore)
+            })
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    event!(Level::ERROR, "error searching dir {err}");
+                    continue;
                 }
-             
+            };
+            event!(Level::TRACE, "enumerated {:?}", entry);
+
+            if entry.depth() < 2 || !entry.file_type().is_file() || entry.file_name().to_str() != Some(".runmd") {
+                continue;
             }
+
+            let folder = match entry.path().parent() {
+                Some(folder) => folder,
+                None => continue,
+            };
+
+            let name = folder
+                .strip_prefix(dir)
+                .ok()
+                .and_then(|n| n.to_str())
+                .map(|n| n.replace(std::path::MAIN_SEPARATOR, "/"))
+                .unwrap_or_default();
+
+            let mut lab = match std::fs::read_to_string(entry.path()) {
+                Ok(content) => Self::parse(content, std::fs::read_to_string(folder.join("lab.toml")).ok()),
+                Err(err) => LabEntry {
+                    error: Some(format!("could not read .runmd, {err}")),
+                    ..Default::default()
+                },
+            };
+
+            lab.path = format!("{name}/.runmd");
+            lab.location = entry.path().to_str().unwrap_or_default().to_string();
+            lab.name = name;
+            labs.push(lab);
         }
 
         labs
     }
-}
\ No newline at end of file
+
+    /// Parses a lab's `.runmd`, and its manifest from `lab_toml` or front matter
+    fn parse(content: impl AsRef<str>, lab_toml: Option<String>) -> LabEntry {
+        let content = content.as_ref();
+        let mut lab = LabEntry::default();
+
+        let front_matter = content
+            .strip_prefix("---")
+            .and_then(|rest| rest.split_once("\n---"))
+            .map(|(front_matter, _)| front_matter);
+
+        let manifest = match (lab_toml, front_matter) {
+            (Some(lab_toml), _) => toml::from_str::<LabManifest>(&lab_toml)
+                .map_err(|e| format!("could not parse lab.toml, {e}")),
+            (None, Some(front_matter)) => serde_yaml::from_str::<LabManifest>(front_matter)
+                .map_err(|e| format!("could not parse front matter, {e}")),
+            (None, None) => Ok(LabManifest::default()),
+        };
+
+        match manifest {
+            Ok(manifest) => lab.manifest = manifest,
+            Err(err) => lab.error = Some(err),
+        }
+
+        if Project::load_content(content.to_string()).is_none() {
+            lab.error = Some("could not parse .runmd".to_string());
+        }
+
+        lab
+    }
+
+    /// Returns the directories to skip, from `{dir}/.labignore`
+    fn ignore_rules(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join(IGNORE_FILE_NAME))
+            .map(|rules| {
+                rules
+                    .lines()
+                    .map(|l| l.trim().trim_matches('/').to_string())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns true if a directory, relative to the search dir, should be skipped
+    fn is_ignored(relative: &Path, ignore: &[String]) -> bool {
+        let name = relative.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let relative = relative.to_str().unwrap_or_default().replace(std::path::MAIN_SEPARATOR, "/");
+
+        name.starts_with('.')
+            || IGNORED_DIRS.contains(&name)
+            || ignore
+                .iter()
+                .any(|rule| rule == name || relative == *rule || relative.starts_with(&format!("{rule}/")))
+    }
+}
@@ -0,0 +1,457 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use tracing::{event, Level};
+
+use crate::{
+    install::Install,
+    registry::{authorize, credentials, trusted_realms},
+};
+
+/// Default directory downloads are cached in
+pub const DEFAULT_CACHE_DIR: &str = ".run/cache";
+
+/// Media types accepted when resolving an OCI manifest
+const OCI_MANIFEST_ACCEPT: &str = "application/vnd.oci.image.manifest.v1+json, application/vnd.oci.artifact.manifest.v1+json, application/vnd.docker.distribution.manifest.v2+json";
+
+/// Annotation used by oras and other tools for the file name of a blob
+pub const OCI_TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// A remote `file_src`, either an https url or an OCI artifact reference
+///
+/// Plain http is only accepted for loopback hosts, ex. a local mirror at `http://127.0.0.1:8080`
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteSource {
+    /// ex. `https://raw.githubusercontent.com/juliusl/chiron/main/lib/sh/fix-jq.sh`
+    Https(String),
+    /// ex. `myregistry.azurecr.io/scripts:v1`, or `oci://localhost:5000/scripts@sha256:...`
+    Oci {
+        registry: String,
+        repository: String,
+        reference: String,
+    },
+}
+
+impl RemoteSource {
+    /// Parses a `file_src`, returns None if the file_src is a local path, or a url w/ a scheme other than https
+    pub fn parse(file_src: impl AsRef<str>) -> Option<Self> {
+        let file_src = file_src.as_ref();
+
+        if file_src.starts_with("https://") || (file_src.starts_with("http://") && Fetch::is_loopback(file_src)) {
+            return Some(RemoteSource::Https(file_src.to_string()));
+        } else if file_src.contains("://") && !file_src.starts_with("oci://") {
+            return None;
+        }
+
+        let explicit = file_src.starts_with("oci://");
+        let reference = file_src.trim_start_matches("oci://");
+        let (registry, path) = reference.split_once('/')?;
+
+        // Without the oci:// prefix, the first segment must look like a host, so that relative paths are not mistaken for registries
+        if !explicit && !Self::is_registry_host(registry) {
+            return None;
+        }
+
+        let (repository, reference) = match path.split_once('@') {
+            Some((repository, digest)) => (repository, digest),
+            None => match path.rsplit_once(':') {
+                Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+                _ if explicit => (path, "latest"),
+                _ => return None,
+            },
+        };
+
+        Some(RemoteSource::Oci {
+            registry: registry.to_string(),
+            repository: repository.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+
+    /// Returns true if `host` is `localhost`, has a port, ex. `registry:5000`, or is a domain name w/ an alphabetic
+    /// top-level domain, ex. `myregistry.azurecr.io`. Directories such as `foo.d` are not hosts
+    fn is_registry_host(host: &str) -> bool {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) => (name, Some(port)),
+            None => (host, None),
+        };
+
+        let labels = name.split('.').collect::<Vec<_>>();
+        let valid_labels = labels
+            .iter()
+            .all(|l| !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+
+        match port {
+            Some(port) => valid_labels && !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()),
+            None if name == "localhost" => true,
+            None => {
+                valid_labels
+                    && labels.len() > 1
+                    && labels
+                        .last()
+                        .map(|tld| tld.len() > 1 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+                        .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Returns true if the source content is pinned by digest
+    pub fn is_pinned(&self) -> bool {
+        match self {
+            RemoteSource::Https(_) => false,
+            RemoteSource::Oci { reference, .. } => reference.starts_with("sha256:"),
+        }
+    }
+
+    /// Returns the name of the source, used in the ledger
+    pub fn name(&self) -> &'static str {
+        match self {
+            RemoteSource::Https(_) => "https",
+            RemoteSource::Oci { .. } => "oci",
+        }
+    }
+
+    /// Returns the file name to install the source as, if one isn't set w/ `file_name`
+    pub fn file_name(&self) -> String {
+        match self {
+            RemoteSource::Https(url) => url
+                .split(['?', '#'])
+                .next()
+                .and_then(|u| u.rsplit('/').next())
+                .unwrap_or_default()
+                .to_string(),
+            RemoteSource::Oci { repository, .. } => {
+                repository.rsplit('/').next().unwrap_or_default().to_string()
+            }
+        }
+    }
+}
+
+/// Fetches remote install sources, caching downloads in `cache_dir`
+///
+/// Content pinned by `sha256` is cached by digest, and is only downloaded once
+///
+pub struct Fetch {
+    cache_dir: PathBuf,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    layers: Option<Vec<Descriptor>>,
+    blobs: Option<Vec<Descriptor>>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    annotations: Option<std::collections::BTreeMap<String, String>>,
+}
+
+impl Fetch {
+    /// Creates a new fetcher that caches downloads in `cache_dir`
+    pub fn new(cache_dir: impl AsRef<str>) -> Self {
+        Self {
+            cache_dir: PathBuf::from(cache_dir.as_ref()),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Fetches the content of a remote source,
+    ///
+    /// If `sha256` is set, the cache is checked first, and the downloaded content must match.
+    /// For OCI sources, `title` selects the blob w/ a matching `org.opencontainers.image.title` annotation,
+    /// otherwise the first blob is used
+    pub async fn fetch(
+        &self,
+        source: &RemoteSource,
+        sha256: Option<String>,
+        title: Option<String>,
+    ) -> Result<Vec<u8>, String> {
+        if let Some(sha256) = sha256.as_ref() {
+            if let Ok(cached) = tokio::fs::read(self.cache_path(sha256)).await {
+                if Install::sha256(&cached).eq_ignore_ascii_case(sha256) {
+                    event!(Level::DEBUG, "found {sha256} in cache");
+                    return Ok(cached);
+                }
+            }
+        }
+
+        let content = match source {
+            RemoteSource::Https(url) => self.get(url, None).await?,
+            RemoteSource::Oci {
+                registry,
+                repository,
+                reference,
+            } => self.pull(registry, repository, reference, title).await?,
+        };
+
+        let digest = Install::sha256(&content);
+        if let Some(sha256) = sha256 {
+            if !digest.eq_ignore_ascii_case(sha256.trim()) {
+                return Err(format!("sha256 mismatch, expected {sha256}, found {digest}"));
+            }
+        }
+
+        let cache_path = self.cache_path(&digest);
+        if let Some(parent) = cache_path.parent() {
+            tokio::fs::create_dir_all(parent).await.ok();
+        }
+        if let Err(err) = tokio::fs::write(&cache_path, &content).await {
+            event!(Level::WARN, "could not cache download, {err}");
+        }
+
+        Ok(content)
+    }
+
+    /// Pulls a blob from an OCI artifact
+    async fn pull(
+        &self,
+        registry: &str,
+        repository: &str,
+        reference: &str,
+        title: Option<String>,
+    ) -> Result<Vec<u8>, String> {
+        let base = Self::registry_url(registry);
+
+        let manifest_url = format!("{base}/v2/{repository}/manifests/{reference}");
+        let manifest = self.get(&manifest_url, Some(OCI_MANIFEST_ACCEPT)).await?;
+        if reference.starts_with("sha256:")
+            && !reference.trim_start_matches("sha256:").eq_ignore_ascii_case(&Install::sha256(&manifest))
+        {
+            return Err(format!("manifest digest mismatch for {repository}@{reference}"));
+        }
+
+        let manifest = serde_json::from_slice::<Manifest>(&manifest)
+            .map_err(|e| format!("could not parse manifest, {e}"))?;
+
+        let descriptors = manifest.blobs.or(manifest.layers).unwrap_or_default();
+        let descriptor = descriptors
+            .iter()
+            .find(|d| match (&title, &d.annotations) {
+                (Some(title), Some(annotations)) => annotations.get(OCI_TITLE_ANNOTATION) == Some(title),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .ok_or(format!("no matching blob found in {repository}:{reference}"))?;
+
+        let blob_url = format!("{base}/v2/{repository}/blobs/{}", descriptor.digest);
+        let blob = self.get(&blob_url, None).await?;
+
+        let expected = descriptor.digest.trim_start_matches("sha256:");
+        if !expected.eq_ignore_ascii_case(&Install::sha256(&blob)) {
+            return Err(format!("blob digest mismatch for {}", descriptor.digest));
+        }
+
+        Ok(blob)
+    }
+
+    /// Sends a GET request, handling a token challenge if the registry requires one
+    async fn get(&self, url: &str, accept: Option<&str>) -> Result<Vec<u8>, String> {
+        let request = || {
+            let mut request = self.client.get(url);
+            if let Some(accept) = accept {
+                request = request.header(reqwest::header::ACCEPT, accept);
+            }
+            request
+        };
+
+        let mut response = request().send().await.map_err(|e| format!("could not get {url}, {e}"))?;
+
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(reqwest::header::WWW_AUTHENTICATE)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.to_string());
+
+            if let Some(auth) = authorize(&self.client, url, challenge, credentials().as_ref(), &trusted_realms()).await {
+                response = auth
+                    .apply(request())
+                    .send()
+                    .await
+                    .map_err(|e| format!("could not get {url}, {e}"))?;
+            }
+        }
+
+        if !response.status().is_success() {
+            return Err(format!("could not get {url}, {}", response.status()));
+        }
+
+        response
+            .bytes()
+            .await
+            .map(|b| b.to_vec())
+            .map_err(|e| format!("could not read {url}, {e}"))
+    }
+
+    /// Local registries are accessed over plain http, ex. `localhost:5000` or `127.0.0.1:5000`
+    pub fn registry_url(registry: &str) -> String {
+        let url = format!("http://{registry}");
+        if Self::is_loopback(&url) {
+            url
+        } else {
+            format!("https://{registry}")
+        }
+    }
+
+    /// Returns true if the host of `url` is exactly `localhost`, or a loopback address
+    fn is_loopback(url: &str) -> bool {
+        match reqwest::Url::parse(url).ok().as_ref().and_then(|u| u.host_str()) {
+            Some("localhost") => true,
+            Some(host) => host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback()),
+            None => false,
+        }
+    }
+
+    /// Returns the path content w/ `sha256` is cached at
+    fn cache_path(&self, sha256: &str) -> PathBuf {
+        self.cache_dir.join("sha256").join(sha256.trim().to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fetch, RemoteSource};
+    use crate::{install::Install, stand_in::StandIn};
+
+    /// Returns an empty cache dir for a test
+    fn cache_dir(test: &str) -> String {
+        let cache_dir = std::env::temp_dir().join(format!("chiron-fetch-{}-{test}", std::process::id()));
+        std::fs::remove_dir_all(&cache_dir).ok();
+        cache_dir.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            RemoteSource::parse("https://example.com/fix.sh"),
+            Some(RemoteSource::Https("https://example.com/fix.sh".to_string()))
+        );
+        assert_eq!(
+            RemoteSource::parse("myregistry.azurecr.io/scripts:v1"),
+            Some(RemoteSource::Oci {
+                registry: "myregistry.azurecr.io".to_string(),
+                repository: "scripts".to_string(),
+                reference: "v1".to_string(),
+            })
+        );
+        assert_eq!(
+            RemoteSource::parse("localhost:5000/labs/azure@sha256:abc"),
+            Some(RemoteSource::Oci {
+                registry: "localhost:5000".to_string(),
+                repository: "labs/azure".to_string(),
+                reference: "sha256:abc".to_string(),
+            })
+        );
+        assert_eq!(
+            RemoteSource::parse("oci://registry/scripts"),
+            Some(RemoteSource::Oci {
+                registry: "registry".to_string(),
+                repository: "scripts".to_string(),
+                reference: "latest".to_string(),
+            })
+        );
+
+        // Plain http is only accepted for loopback hosts
+        assert_eq!(
+            RemoteSource::parse("http://127.0.0.1:8080/fix.sh"),
+            Some(RemoteSource::Https("http://127.0.0.1:8080/fix.sh".to_string()))
+        );
+        assert_eq!(RemoteSource::parse("http://example.com/fix.sh"), None);
+        assert_eq!(RemoteSource::parse("http://localhost.evil.com/fix.sh"), None);
+        assert_eq!(RemoteSource::parse("ftp://example.com/fix.sh"), None);
+
+        // Local paths
+        assert_eq!(RemoteSource::parse("lib/sh/fix-jq.sh"), None);
+        assert_eq!(RemoteSource::parse("foo.d/bar:baz"), None);
+        assert_eq!(RemoteSource::parse("scripts.v2/install:latest"), None);
+        assert_eq!(RemoteSource::parse("registry/scripts:v1"), None);
+    }
+
+    #[test]
+    fn test_registry_url() {
+        for registry in ["localhost", "localhost:5000", "127.0.0.1:5000", "[::1]:5000"] {
+            assert_eq!(Fetch::registry_url(registry), format!("http://{registry}"));
+        }
+
+        for registry in ["localhost.evil.com", "localhost.evil.com:5000", "127.0.0.1.nip.io", "myregistry.azurecr.io"] {
+            assert_eq!(Fetch::registry_url(registry), format!("https://{registry}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_https_pinned() {
+        let stand_in = StandIn::start().await;
+        stand_in.add_file("scripts/fix.sh", "echo fixed");
+        let source = RemoteSource::parse(stand_in.url("scripts/fix.sh")).expect("should be a remote source");
+        let sha256 = Install::sha256("echo fixed");
+
+        let fetch = Fetch::new(cache_dir("https_pinned"));
+        let content = fetch.fetch(&source, Some(sha256.to_string()), None).await;
+        assert_eq!(content, Ok(b"echo fixed".to_vec()));
+        assert!(fetch.cache_path(&sha256).exists(), "download should be cached by digest");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_https_sha256_mismatch() {
+        let stand_in = StandIn::start().await;
+        stand_in.add_file("scripts/fix.sh", "echo tampered");
+        let source = RemoteSource::parse(stand_in.url("scripts/fix.sh")).expect("should be a remote source");
+        let sha256 = Install::sha256("echo fixed");
+
+        let fetch = Fetch::new(cache_dir("https_mismatch"));
+        let err = fetch
+            .fetch(&source, Some(sha256.to_string()), None)
+            .await
+            .expect_err("should not accept content that does not match the pin");
+        assert!(err.starts_with("sha256 mismatch"), "{err}");
+        assert!(!fetch.cache_path(&sha256).exists());
+        assert!(!fetch.cache_path(&Install::sha256("echo tampered")).exists());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cache_hit() {
+        let stand_in = StandIn::start().await;
+        stand_in.add_file("scripts/fix.sh", "echo fixed");
+        let source = RemoteSource::parse(stand_in.url("scripts/fix.sh")).expect("should be a remote source");
+        let sha256 = Install::sha256("echo fixed");
+
+        let fetch = Fetch::new(cache_dir("cache_hit"));
+        for _ in 0..2 {
+            let content = fetch.fetch(&source, Some(sha256.to_string()), None).await;
+            assert_eq!(content, Ok(b"echo fixed".to_vec()));
+        }
+        assert_eq!(stand_in.requests(), vec!["GET /scripts/fix.sh"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_oci_blob() {
+        let stand_in = StandIn::start().await;
+        let digest = stand_in.add_artifact("scripts/fix", "v1", "fix.sh", b"echo fixed");
+
+        let fetch = Fetch::new(cache_dir("oci_blob"));
+
+        let source = RemoteSource::parse(format!("{}/scripts/fix:v1", stand_in.host)).expect("should be a remote source");
+        assert!(!source.is_pinned());
+        let content = fetch.fetch(&source, None, Some("fix.sh".to_string())).await;
+        assert_eq!(content, Ok(b"echo fixed".to_vec()));
+
+        let source =
+            RemoteSource::parse(format!("{}/scripts/fix@{digest}", stand_in.host)).expect("should be a remote source");
+        assert!(source.is_pinned());
+        let content = fetch.fetch(&source, None, None).await;
+        assert_eq!(content, Ok(b"echo fixed".to_vec()));
+
+        let err = fetch
+            .fetch(&source, None, Some("missing.sh".to_string()))
+            .await
+            .expect_err("should not find a blob w/ a different title");
+        assert!(err.starts_with("no matching blob"), "{err}");
+    }
+}
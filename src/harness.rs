@@ -0,0 +1,225 @@
+use std::{fmt::Write, time::Instant};
+
+use lifec::plugins::{Plugin, Project};
+use tracing::{event, Level};
+
+use crate::{
+    assert::{Assert, Assertion},
+    runner::Runner,
+};
+
+/// Test harness for labs,
+///
+/// Runs a lab's engines w/ a temporary directory as the work_dir, evaluates every `assert` block in the lab,
+/// and writes the results as JUnit XML so lab regressions show up in CI
+///
+pub struct Harness {
+    /// Name of the lab, used as the test suite name
+    lab: String,
+    runner: Runner,
+}
+
+/// Result of a single test case
+///
+#[derive(Debug, Default)]
+pub struct TestCase {
+    classname: String,
+    name: String,
+    time: f32,
+    failure: Option<String>,
+}
+
+impl Harness {
+    /// Loads the lab, returns None if the lab could not be loaded
+    pub fn load(lab: impl AsRef<str>, lab_dir: Option<String>) -> Option<Self> {
+        let source = Runner::load(lab.as_ref(), lab_dir)?;
+        Project::load_content(source.clone()).map(|project| Self {
+            lab: lab.as_ref().to_string(),
+            runner: Runner::new(project, source),
+        })
+    }
+
+    /// Runs the engines and evaluates assertions, writing JUnit XML to `junit`,
+    ///
+    /// If no engines are passed, every engine in the lab that does not host a server is run. Returns true if all test cases passed
+    pub fn test(self, engines: Vec<String>, junit: impl AsRef<str>) -> bool {
+        let test_dir = std::env::temp_dir().join(format!("chiron-test-{}", std::process::id()));
+
+        if let Err(err) = std::fs::create_dir_all(&test_dir) {
+            eprintln!("could not create test dir {:?}, {err}", test_dir);
+            return false;
+        }
+        eprintln!("running {} in {:?}", self.lab, test_dir);
+
+        let Self { lab, runner } = self;
+        let mut runner = runner.with_work_dir(&test_dir);
+
+        let engines = if engines.is_empty() {
+            runner
+                .project()
+                .iter_block()
+                .filter(|(_, block)| block.get_block("call").is_some())
+                .map(|(block_name, _)| block_name.to_string())
+                .filter(|engine| {
+                    let hosts_server = runner.hosts_server(engine);
+                    if hosts_server {
+                        eprintln!("skipping engine {engine}, it hosts a server");
+                    }
+                    !hosts_server
+                })
+                .collect()
+        } else {
+            engines
+        };
+
+        let mut test_cases = vec![];
+        for engine in engines {
+            let started = Instant::now();
+            let passed = runner.run(vec![engine.to_string()]);
+
+            test_cases.push(TestCase {
+                classname: lab.to_string(),
+                name: format!("engine {engine}"),
+                time: started.elapsed().as_secs_f32(),
+                failure: if passed {
+                    None
+                } else {
+                    Some(format!("engine {engine} failed"))
+                },
+            });
+        }
+
+        let assert_blocks = runner
+            .project()
+            .iter_block()
+            .filter_map(|(block_name, block)| {
+                block
+                    .get_block(Assert::symbol())
+                    .map(|graph| (block_name.to_string(), graph))
+            })
+            .collect::<Vec<_>>();
+
+        for (block_name, mut graph) in assert_blocks {
+            let started = Instant::now();
+
+            // Assert blocks called by an engine were already evaluated, so their results are reused
+            let assertions = match runner
+                .outputs()
+                .get(&block_name)
+                .and_then(|o| o.find_binary("assertions"))
+            {
+                Some(results) => serde_json::from_slice::<Vec<Assertion>>(&results).unwrap_or_default(),
+                None => {
+                    if graph.find_text("work_dir").is_none() {
+                        graph.add_text_attr("work_dir", test_dir.to_string_lossy());
+                    }
+                    runner
+                        .tokio_runtime()
+                        .block_on(Assert::evaluate(&block_name, &graph, runner.outputs()))
+                }
+            };
+            let time = started.elapsed().as_secs_f32();
+
+            for Assertion { name, kind, error, .. } in assertions {
+                test_cases.push(TestCase {
+                    classname: format!("{lab}.{block_name}"),
+                    name: format!("{name} {kind}"),
+                    time,
+                    failure: error,
+                });
+            }
+        }
+
+        let failures = test_cases.iter().filter(|t| t.failure.is_some()).count();
+        for TestCase {
+            classname,
+            name,
+            failure,
+            ..
+        } in test_cases.iter()
+        {
+            match failure {
+                Some(failure) => eprintln!("fail {classname} {name}, {failure}"),
+                None => eprintln!("pass {classname} {name}"),
+            }
+        }
+        eprintln!("{} passed, {failures} failed", test_cases.len() - failures);
+
+        match std::fs::write(junit.as_ref(), Self::junit(&lab, &test_cases)) {
+            Ok(_) => eprintln!("wrote test results to {}", junit.as_ref()),
+            Err(err) => {
+                event!(Level::ERROR, "could not write test results to {}, {err}", junit.as_ref());
+            }
+        }
+
+        if failures == 0 {
+            std::fs::remove_dir_all(&test_dir).ok();
+        } else {
+            eprintln!("keeping {:?} for inspection", test_dir);
+        }
+
+        failures == 0 && !test_cases.is_empty()
+    }
+
+    /// Formats test cases as a JUnit XML test suite
+    fn junit(suite: impl AsRef<str>, test_cases: &[TestCase]) -> String {
+        let failures = test_cases.iter().filter(|t| t.failure.is_some()).count();
+        let time: f32 = test_cases.iter().map(|t| t.time).sum();
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            xml,
+            "<testsuites tests=\"{}\" failures=\"{failures}\" time=\"{time:.3}\">",
+            test_cases.len()
+        )
+        .ok();
+        writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" time=\"{time:.3}\">",
+            escape(suite.as_ref()),
+            test_cases.len()
+        )
+        .ok();
+
+        for TestCase {
+            classname,
+            name,
+            time,
+            failure,
+        } in test_cases
+        {
+            write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{time:.3}\"",
+                escape(classname),
+                escape(name)
+            )
+            .ok();
+
+            match failure {
+                Some(failure) => {
+                    writeln!(xml, ">").ok();
+                    writeln!(xml, "      <failure message=\"{}\"/>", escape(failure)).ok();
+                    writeln!(xml, "    </testcase>").ok();
+                }
+                None => {
+                    writeln!(xml, "/>").ok();
+                }
+            }
+        }
+
+        writeln!(xml, "  </testsuite>").ok();
+        writeln!(xml, "</testsuites>").ok();
+        xml
+    }
+}
+
+/// Escapes text for an XML attribute
+fn escape(text: impl AsRef<str>) -> String {
+    text.as_ref()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
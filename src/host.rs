@@ -1,12 +1,19 @@
 use imgui::{MenuItem, Window};
 use lifec::{
     editor::RuntimeEditor,
-    plugins::ThunkContext,
-    AttributeGraph, DispatcherBuilder, Extension, Resources, Runtime,
+    plugins::Project,
+    AttributeGraph, DispatcherBuilder, Extension, Runtime,
     RuntimeDispatcher, World, WorldExt,
 };
 
-use crate::design::Design;
+use std::{path::Path, sync::mpsc::{channel, Receiver}};
+
+use tracing::{event, Level};
+
+use crate::{
+    resources::Resolver,
+    watch::{HotReload, Reload},
+};
 
 /// This type wraps the runtime editor as the underlying extension
 /// Can be executed standalone w/o the main window
@@ -14,11 +21,15 @@ pub struct Host(
     pub RuntimeEditor,
     /// Clear entities
     Option<()>,
+    /// Reloads the project when the project file changes
+    Option<(HotReload, Receiver<Reload>)>,
+    /// Prepares a reloaded project the same way the first project was, ex. enabling dry_run
+    Option<Box<dyn Fn(Project) -> Project + Send>>,
 );
 
 impl From<RuntimeEditor> for Host {
     fn from(editor: RuntimeEditor) -> Self {
-        Host(editor, None)
+        Host(editor, None, None, None)
     }
 }
 
@@ -35,6 +46,45 @@ impl AsRef<Runtime> for Host {
 }
 
 impl Host {
+    /// Watches the project file, reloading the project in the runtime editor each time the file changes
+    pub fn watch(mut self, project_src: impl AsRef<Path>) -> Self {
+        let (sender, receiver) = channel();
+        match HotReload::watch(project_src.as_ref(), move |reload| {
+            sender.send(reload).ok();
+        }) {
+            Ok(hot_reload) => {
+                self.2 = Some((hot_reload, receiver));
+            }
+            Err(err) => {
+                event!(Level::DEBUG, "not watching {:?}, {err}", project_src.as_ref());
+            }
+        }
+        self
+    }
+
+    /// Sets how a reloaded project is prepared before it replaces the current project,
+    ///
+    /// Should match how the first project was prepared, ex. w/ `enable_dry_run`, so that a reload doesn't drop those changes
+    pub fn prepare_reloads(mut self, prepare: impl Fn(Project) -> Project + Send + 'static) -> Self {
+        self.3 = Some(Box::new(prepare));
+        self
+    }
+
+    /// Replaces the project w/ a reloaded project, w/ the same pipeline as `create_runtime`
+    fn reload_project(&mut self, content: impl AsRef<str>) -> bool {
+        match Project::load_content(content.as_ref().to_string()) {
+            Some(project) => {
+                let project = match self.3.as_ref() {
+                    Some(prepare) => prepare(project),
+                    None => project,
+                };
+                *self.0.project_mut() = Resolver::apply(project);
+                true
+            }
+            None => false,
+        }
+    }
+
     fn load_project_from_content(&mut self, content: impl AsRef<str>) -> bool {
         let mut graph = AttributeGraph::from(0);
         if graph.batch_mut(content.as_ref()).is_ok() {
@@ -89,25 +139,13 @@ impl Extension for Host {
             .size([300.0, 300.0], imgui::Condition::Appearing)
             .build(ui, || {
                 if ui.button("Start help portal") {
-                    let tokio_runtime = app_world.read_resource::<tokio::runtime::Runtime>();
-
-                    tokio_runtime.block_on(async {
-                        if let Some(portal) = Resources("design")
-                            .read_binary::<Design>(
-                                &ThunkContext::default(),
-                                &"design/portal/.runmd".to_string(),
-                            )
-                            .await
-                        {
-                            if let Some(data) = String::from_utf8(portal.to_vec()).ok() {
-                                if self.load_project_from_content(data) {
-                                    if let Some(created) = self.0.create_default(app_world) {
-                                        Runtime::start_event(created, app_world);
-                                    }
-                                }
+                    if let Some(portal) = Resolver::new().get_string("design/portal/.runmd") {
+                        if self.load_project_from_content(portal) {
+                            if let Some(created) = self.0.create_default(app_world) {
+                                Runtime::start_event(created, app_world);
                             }
                         }
-                    });
+                    }
                 }
 
                 ui.text_wrapped(
@@ -123,6 +161,20 @@ impl Extension for Host {
     }
 
     fn on_run(&'_ mut self, app_world: &World) {
+        let reloads = self
+            .2
+            .as_ref()
+            .map(|(_, receiver)| receiver.try_iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        for Reload { path, content } in reloads {
+            if self.reload_project(content) {
+                event!(Level::INFO, "reloaded project from {:?}", path);
+            } else {
+                event!(Level::ERROR, "could not reload project from {:?}", path);
+            }
+        }
+
         self.0.on_run(app_world);
     }
 
@@ -0,0 +1,36 @@
+use lifec::plugins::{AsyncContext, Plugin, ThunkContext};
+use lifec::{Component, DenseVecStorage};
+use lifec_poem::AppHost;
+use rocks_db_openapi::Inspector as RocksDbInspector;
+
+/// Hosts the connected registry rocksdb inspector as an `app_host` app, so a lab can launch it next to the mirror, ex.
+///
+/// ````md
+/// ``` runmd_create
+/// ``` connected_registry inspector
+/// add address                     .text localhost:8000
+/// add config_store                .text /var/acr/data/rocksdb/config
+/// add metadata_store              .text /var/acr/data/rocksdb/metadata
+/// add dash_dir                    .text hack/rocks_db_openapi/lib/elm/dash
+/// ```
+/// ````
+///
+/// Store paths that aren't set use the inspector's defaults. Only available when chiron is built w/ the `inspector` feature
+///
+#[derive(Component, Default)]
+#[storage(DenseVecStorage)]
+pub struct Inspector;
+
+impl Plugin<ThunkContext> for Inspector {
+    fn symbol() -> &'static str {
+        "inspector"
+    }
+
+    fn description() -> &'static str {
+        "Hosts the connected registry rocksdb inspector on {address}, w/ {config_store}, {metadata_store} and {dash_dir}"
+    }
+
+    fn call_with_context(context: &mut ThunkContext) -> Option<AsyncContext> {
+        AppHost::<RocksDbInspector>::call_with_context(context)
+    }
+}
@@ -1,21 +1,48 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+};
 
 use lifec::{
-    plugins::{combine, OpenFile, Plugin, ThunkContext, WriteFile},
-    Component, DenseVecStorage, Resources,
+    plugins::{Plugin, ThunkContext},
+    AttributeGraph, Component, DenseVecStorage, Value,
+};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tinytemplate::{format_unescaped, TinyTemplate};
+use tracing::{event, Level};
+
+use crate::{
+    fetch::{Fetch, RemoteSource, DEFAULT_CACHE_DIR},
+    ledger::{Ledger, LedgerEntry},
+    resources::Resolver,
 };
-use rust_embed::RustEmbed;
 
 /// Installs a file
+///
+/// If `enable_template` is set, the file is rendered as a template before it is written. Variables are
+/// the text attributes of the block, ex. `{ registry_name }`, and env vars, ex. `{ env.HOME }`
+///
+/// Other optional attributes,
+/// - `sha256`, the expected hash of the source file, the install fails if the content does not match
+/// - `mode`, octal permissions to set on the installed file, ex. `755`. Shell scripts default to `755`
+/// - `backup`, if enabled, a file being replaced by the first install is copied to `{file_name}.bak`
+/// - `file_name`, the name to install the file as, defaults to the file name of `file_src`
+///
+/// `file_src` can also be an https url, or an OCI artifact reference, ex. `myregistry.azurecr.io/scripts:v1`.
+/// Remote sources must be pinned w/ `sha256` or an OCI digest, unless `allow_unpinned` is enabled. Downloads
+/// are cached in `cache_dir`, `.run/cache` by default. `oci_title` selects a blob by its title annotation
+///
+/// Local sources are resolved w/ `Resolver`, so embedded paths under `lib/sh`, `lib/cloud_init`, `lib/elm` and `design`
+/// can be installed w/o the repo on disk. Overlay directories are read from `{prefix}_overlay`, ex. `lib_sh_overlay`
+///
+/// If the destination already has identical content, the write is skipped. Every file written is recorded in
+/// the work_dir's install ledger, so that it can be removed w/ `uninstall`
 #[derive(Component, Default)]
 #[storage(DenseVecStorage)]
 pub struct Install;
 
-
-#[derive(RustEmbed)]
-#[folder = "lib/sh"]
-struct Shell;
-
 impl Plugin<ThunkContext> for Install {
     fn symbol() -> &'static str {
         "install"
@@ -28,47 +55,374 @@ impl Plugin<ThunkContext> for Install {
     fn call_with_context(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
         let block_name = context.block.block_name.to_string();
 
-        if let Some(src_dir) = context.as_ref().find_text("src_dir") {
-            if let Some(tool_name) = context.as_ref().find_text("tool_name") {
-                if let Some(ext) = context.as_ref().find_text("ext") {
+        let file_src = if let Some(src_dir) = context.as_ref().find_text("src_dir") {
+            match (
+                context.as_ref().find_text("tool_name"),
+                context.as_ref().find_text("ext"),
+            ) {
+                (Some(tool_name), Some(ext)) => {
                     let src_type = context
                         .as_ref()
                         .find_text("src_type")
                         .unwrap_or("install".to_string());
 
                     let file_src = format!("{src_dir}/{tool_name}/{src_type}-{block_name}.{ext}");
-                    context.as_mut().add_text_attr("file_src", file_src);
+                    context.as_mut().add_text_attr("file_src", &file_src);
+                    Some(file_src)
+                }
+                _ => None,
+            }
+        } else {
+            context.as_ref().find_text("file_src")
+        };
 
-                    return combine::<OpenFile, WriteFile>()(context);
+        match file_src {
+            Some(file_src) if context.as_ref().is_enabled("dry_run").unwrap_or_default() => {
+                Self::dry_run(context, file_src)
+            }
+            Some(file_src) => context.clone().task(|_| {
+                let mut tc = context.clone();
+                async move {
+                    match Self::install(&block_name, tc.as_ref(), &file_src).await {
+                        Ok((file_dst, content, written)) => {
+                            let status = if written { "installed" } else { "unchanged" };
+                            tc.update_status_only(format!("{status} {:?}", file_dst)).await;
+                            tc.as_mut().add_binary_attr("content", content);
+                            Some(tc)
+                        }
+                        Err(err) => {
+                            eprintln!("install: could not install {file_src}, {err}");
+                            event!(Level::ERROR, "could not install {file_src}, {err}");
+                            None
+                        }
+                    }
                 }
+            }),
+            None => {
+                eprintln!("install skipped");
+                None
             }
-        } else if let Some(file_src) = context.as_ref().find_text("file_src") {
+        }
+    }
+}
+
+
+impl Install {
+    /// Prints the file that would be installed, w/o writing anything
+    fn dry_run(context: &mut ThunkContext, file_src: impl AsRef<str>) -> Option<lifec::plugins::AsyncContext> {
+        let file_src = file_src.as_ref();
+        let file_dst = Self::file_dst(context.as_ref(), file_src);
+
+        let resolver = Resolver::from(context.as_ref());
 
-            if let Some(handle) = context.handle() {
-                handle.block_on(async {
-                    Self::resolve_lib_file(context, file_src).await;
-                });
+        let source = match (resolver.origin(file_src), RemoteSource::parse(file_src)) {
+            (Some(origin), _) => origin.as_str(),
+            (None, Some(remote)) => remote.name(),
+            (None, None) => "missing",
+        };
+
+        eprintln!("install (dry_run): would write {:?} from {file_src} ({source})", file_dst);
+
+        match Self::mode(context.as_ref(), &file_dst) {
+            Ok(Some(mode)) => eprintln!("  would set mode {:o}", mode),
+            Ok(None) => {}
+            Err(err) => eprintln!("  would fail, {err}"),
+        }
+
+        if let (Some(expected), Ok((content, _))) = (context.as_ref().find_text("sha256"), Self::read_file(context.as_ref(), file_src)) {
+            let actual = Self::sha256(&content);
+            if actual.eq_ignore_ascii_case(expected.trim()) {
+                eprintln!("  sha256 matches");
+            } else {
+                eprintln!("  sha256 would fail, expected {expected}, found {actual}");
             }
+        }
 
-            return combine::<OpenFile, WriteFile>()(context);
+        if context.as_ref().is_enabled("enable_template").unwrap_or_default() {
+            match Self::read_file(context.as_ref(), file_src)
+                .ok()
+                .and_then(|(c, _)| String::from_utf8(c).ok())
+                .map(|c| Self::render(context.as_ref(), c))
+            {
+                Some(Ok(_)) => eprintln!("  template renders"),
+                Some(Err(err)) => eprintln!("  template would fail, {err}"),
+                None => eprintln!("  template could not be read"),
+            }
         }
 
-        eprintln!("install skipped");
-        None
+        context.clone().task(|_| {
+            let tc = context.clone();
+            async move { Some(tc) }
+        })
     }
-}
 
+    /// Installs `file_src` by calling the plugin w/ `config`, so that `sha256`, `dry_run` and the ledger apply,
+    ///
+    /// Returns the path `file_src` is installed to, or would be installed to if `dry_run` is enabled
+    pub async fn install_with(
+        block_name: impl AsRef<str>,
+        config: &AttributeGraph,
+        file_src: impl AsRef<str>,
+    ) -> Result<PathBuf, String> {
+        let file_src = file_src.as_ref();
 
-impl Install {
-    async fn resolve_lib_file(context: &mut ThunkContext, file_src: impl AsRef<str>) {
-        let path_buf = PathBuf::from(file_src.as_ref());
-        if !path_buf.exists() {
-            if file_src.as_ref().starts_with("lib/sh") {
-                let file_name = file_src.as_ref().trim_start_matches("lib/sh/");
-                if let Some(file) = Resources("lib/sh").read_binary::<Shell>(context, &file_name.to_string()).await {
-                    context.as_mut().add_binary_attr("content", file.to_vec());
+        let mut tc = ThunkContext::default();
+        tc.block.block_name = block_name.as_ref().to_string();
+        tc.handle = Some(tokio::runtime::Handle::current());
+        *tc.as_mut() = config.clone();
+        tc.as_mut().add_text_attr("file_src", file_src);
+
+        match Self::call_with_context(&mut tc) {
+            Some((task, _cancel)) => match task.await {
+                Ok(Some(result)) => Ok(Self::file_dst(result.as_ref(), file_src)),
+                Ok(None) => Err(format!("could not install {file_src}")),
+                Err(err) => Err(format!("install task failed, {err}")),
+            },
+            None => Err("install did not start".to_string()),
+        }
+    }
+
+    /// Resolves, verifies, renders and writes `file_src` to `{work_dir}/{file_name}`, and records it in the work_dir's ledger
+    ///
+    /// Returns the installed path, the installed content, and true if the file was written
+    pub async fn install(
+        block_name: impl AsRef<str>,
+        graph: &AttributeGraph,
+        file_src: impl AsRef<str>,
+    ) -> Result<(PathBuf, Vec<u8>, bool), String> {
+        let file_src = file_src.as_ref();
+        let work_dir = graph.find_text("work_dir").unwrap_or_default();
+        let file_dst = Self::file_dst(graph, file_src);
+        let mode = Self::mode(graph, &file_dst)?;
+
+        let remote = RemoteSource::parse(file_src).filter(|_| !Resolver::from(graph).exists(file_src));
+        let (mut content, source) = match remote {
+            Some(remote) => {
+                let sha256 = graph.find_text("sha256");
+                if sha256.is_none() && !remote.is_pinned() && !graph.is_enabled("allow_unpinned").unwrap_or_default() {
+                    return Err(format!(
+                        "{file_src} is not pinned, add a sha256 attribute or enable allow_unpinned"
+                    ));
                 }
+
+                let cache_dir = graph
+                    .find_text("cache_dir")
+                    .unwrap_or(DEFAULT_CACHE_DIR.to_string());
+                let content = Fetch::new(cache_dir)
+                    .fetch(&remote, sha256, graph.find_text("oci_title"))
+                    .await?;
+                (content, remote.name())
+            }
+            None if file_src.contains("://") && !Resolver::from(graph).exists(file_src) => {
+                return Err(format!("{file_src} is not a supported remote source, use an https url or an OCI reference"));
+            }
+            None => Self::read_file(graph, file_src).map_err(|e| e.to_string())?,
+        };
+        let source = if graph.find_text("src_dir").is_some() {
+            "pattern"
+        } else {
+            source
+        };
+
+        if let Some(expected) = graph.find_text("sha256") {
+            let actual = Self::sha256(&content);
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                return Err(format!("sha256 mismatch, expected {expected}, found {actual}"));
             }
         }
+
+        if graph.is_enabled("enable_template").unwrap_or_default() {
+            let template = String::from_utf8(content).map_err(|e| e.to_string())?;
+            content = Self::render(graph, template)?.into_bytes();
+        }
+
+        // A file already in the ledger was written by a previous install, so only the first install keeps a backup
+        let installed = Ledger::load(&work_dir).await?.entry(&file_dst).is_some();
+
+        let mut backup = None;
+        let existing = tokio::fs::read(&file_dst).await.ok();
+        let written = match existing {
+            Some(existing) if existing == content => {
+                event!(Level::DEBUG, "{:?} is unchanged, skipping write", file_dst);
+                false
+            }
+            existing => {
+                if existing.is_some() && !installed && graph.is_enabled("backup").unwrap_or_default() {
+                    let mut backup_path = std::env::current_dir()
+                        .map_err(|e| e.to_string())?
+                        .join(&file_dst)
+                        .into_os_string();
+                    backup_path.push(".bak");
+                    tokio::fs::copy(&file_dst, &backup_path)
+                        .await
+                        .map_err(|e| format!("could not backup {:?}, {e}", file_dst))?;
+                    backup = backup_path.to_str().map(|b| b.to_string());
+                }
+
+                tokio::fs::create_dir_all(&work_dir).await.map_err(|e| e.to_string())?;
+                tokio::fs::write(&file_dst, &content)
+                    .await
+                    .map_err(|e| format!("could not write {:?}, {e}", file_dst))?;
+                true
+            }
+        };
+
+        if let Some(mode) = mode {
+            Self::set_mode(&file_dst, mode).await.map_err(|e| format!("could not set mode, {e}"))?;
+        }
+
+        if written {
+            Ledger::record(
+                &work_dir,
+                LedgerEntry {
+                    block_name: block_name.as_ref().to_string(),
+                    file_src: file_src.to_string(),
+                    source: source.to_string(),
+                    file_dst: file_dst.to_str().unwrap_or_default().to_string(),
+                    sha256: Self::sha256(&content),
+                    backup,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        Ok((file_dst, content, written))
+    }
+
+    /// Renders content as a template against the text attributes in `graph`, and env vars,
+    ///
+    /// Returns an error listing every missing variable, if any variables are not defined
+    pub fn render(graph: &AttributeGraph, template: impl AsRef<str>) -> Result<String, String> {
+        let mut values = serde_json::Map::new();
+        for attribute in graph.iter_attributes() {
+            if let Value::TextBuffer(text) = attribute.value() {
+                values.insert(attribute.name().to_string(), serde_json::Value::String(text.to_string()));
+            }
+        }
+
+        let env = std::env::vars()
+            .map(|(name, value)| (name, serde_json::Value::String(value)))
+            .collect::<serde_json::Map<_, _>>();
+        values.insert("env".to_string(), serde_json::Value::Object(env));
+        let values = serde_json::Value::Object(values);
+
+        let variables = Regex::new(r"(?:^|[^\\{])\{\s*([A-Za-z_][A-Za-z0-9_\.]*)\s*(?:\|[^}]*)?\}")
+            .expect("should be a valid regex");
+        let missing = variables
+            .captures_iter(template.as_ref())
+            .filter_map(|c| c.get(1).map(|m| m.as_str().to_string()))
+            .filter(|name| {
+                name.split('.')
+                    .try_fold(&values, |v, key| v.get(key))
+                    .is_none()
+            })
+            .collect::<BTreeSet<_>>();
+
+        if !missing.is_empty() {
+            return Err(format!(
+                "missing template variables: {}",
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+
+        let mut tt = TinyTemplate::new();
+        tt.set_default_formatter(&format_unescaped);
+        tt.add_template("install", template.as_ref())
+            .map_err(|e| e.to_string())?;
+        tt.render("install", &values).map_err(|e| e.to_string())
+    }
+
+    /// Returns the hex encoded sha256 digest of content
+    pub fn sha256(content: impl AsRef<[u8]>) -> String {
+        format!("{:x}", Sha256::digest(content.as_ref()))
+    }
+
+    /// Reads `file_src` w/ the resolver for `graph`, from an overlay, from disk, or from the embedded resources
+    ///
+    /// Returns the content, and where it was read from, either `overlay`, `filesystem` or `embedded`
+    fn read_file(graph: &AttributeGraph, file_src: impl AsRef<str>) -> io::Result<(Vec<u8>, &'static str)> {
+        Resolver::from(graph)
+            .get(file_src.as_ref())
+            .map(|(content, origin)| (content, origin.as_str()))
+            .ok_or(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("could not find {}", file_src.as_ref()),
+            ))
     }
-}
\ No newline at end of file
+
+    /// Returns the path `file_src` is installed to, `{work_dir}/{file_name}`
+    ///
+    /// If `file_name` is not set, the file name of `file_src` is used
+    fn file_dst(graph: &AttributeGraph, file_src: impl AsRef<str>) -> PathBuf {
+        let work_dir = graph.find_text("work_dir").unwrap_or_default();
+        let file_name = match (graph.find_text("file_name"), RemoteSource::parse(file_src.as_ref())) {
+            (Some(file_name), _) => file_name,
+            (None, Some(remote)) if !PathBuf::from(file_src.as_ref()).exists() => remote.file_name(),
+            (None, _) => PathBuf::from(file_src.as_ref())
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or_default()
+                .to_string(),
+        };
+
+        PathBuf::from(work_dir).join(file_name)
+    }
+
+    /// Returns the mode to set on the installed file, shell scripts are executable by default
+    ///
+    /// The default is based on the installed name, so a template like `acr-login.sh.tmpl` installed as `acr-login.sh` is executable.
+    /// Returns an error if `mode` is not a valid octal mode, ex. `755`
+    fn mode(graph: &AttributeGraph, file_dst: &Path) -> Result<Option<u32>, String> {
+        match graph.find_text("mode") {
+            Some(mode) => u32::from_str_radix(mode.trim(), 8)
+                .ok()
+                .filter(|m| *m <= 0o7777)
+                .map(Some)
+                .ok_or(format!("invalid mode `{mode}`, expected octal permissions, ex. 755")),
+            None if file_dst.extension().is_some_and(|e| e == "sh") => Ok(Some(0o755)),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(unix)]
+    async fn set_mode(path: &PathBuf, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await
+    }
+
+    #[cfg(not(unix))]
+    async fn set_mode(_: &PathBuf, _: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lifec::AttributeGraph;
+
+    use super::Install;
+
+    #[test]
+    fn test_mode() {
+        let mut graph = AttributeGraph::from(0);
+        graph.add_text_attr("file_name", "acr-login.sh");
+
+        let file_dst = Install::file_dst(&graph, "lib/sh/acr-login.sh.tmpl");
+        assert_eq!(file_dst, Path::new("acr-login.sh"));
+        assert_eq!(Install::mode(&graph, &file_dst), Ok(Some(0o755)));
+
+        assert_eq!(Install::mode(&graph, Path::new("acr-login.sh.tmpl")), Ok(None));
+        assert_eq!(Install::mode(&graph, Path::new("user-data.yml")), Ok(None));
+
+        let mut graph = AttributeGraph::from(0);
+        graph.add_text_attr("mode", "644");
+        assert_eq!(Install::mode(&graph, &file_dst), Ok(Some(0o644)));
+
+        let mut graph = AttributeGraph::from(0);
+        graph.add_text_attr("mode", "rwx");
+        assert!(Install::mode(&graph, &file_dst).is_err());
+    }
+}
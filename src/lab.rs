@@ -1,39 +1,141 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::{create_runtime, design::Design, host::Host};
-use futures_util::StreamExt;
+use crate::{
+    check::{Check, Expectation, FIX_WORK_DIR},
+    create_runtime,
+    design::Design,
+    host::Host,
+    registry::Registry,
+    resources::Resolver,
+    watch::{HotReload, Reload},
+};
+use futures_util::{SinkExt, StreamExt};
 use lifec::{
     editor::{RuntimeEditor, Call},
-    plugins::{Expect, Plugin, Project, ThunkContext},
-    AttributeGraph, Resources, Runtime, RuntimeDispatcher, Value,
+    plugins::{Plugin, Project, ThunkContext},
+    AttributeGraph, Runtime, RuntimeDispatcher, Value,
 };
 use lifec_poem::WebApp;
 use poem::{
-    endpoint::EmbeddedFilesEndpoint,
     get, handler,
+    http::StatusCode,
+    post,
     web::{
         websocket::{Message, WebSocket},
         Data, Html, Json, Path,
     },
-    EndpointExt, IntoResponse, Route,
+    EndpointExt, IntoResponse, Response, Route,
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 use tracing::{event, Level};
 
 /// Lab component hosts a portal for browsing .runmd in the design folder
+///
+/// Labs in `lab_dir` are also listed, and if `lab_registry` is set, ex. `localhost:5000`, every lab pushed to the registry's catalog.
+/// The catalog is listed at most once every `lab_registry_ttl` seconds, 60 by default
+///
+/// Unless `hot_reload` is disabled, `lab_dir` is watched, and each open portal is sent `reloaded {name}` when its lab changes
+///
+/// Fix scripts install packages on the host, so `/lab/{name}/fix/{dep}` is only served if `enable_lab_fix` is set
 #[derive(Default)]
-pub struct Lab(ThunkContext);
+pub struct Lab(ThunkContext, Reloads, CatalogCache);
 
-impl Lab {
-    async fn get_project(project_src: impl AsRef<str>) -> Option<Project> {
-        if project_src.as_ref().starts_with("design/") {
-            let path = project_src.as_ref();
-            if let Some(content) = Resources("design").read_binary::<Design>(&ThunkContext::default(), &path.to_string()).await {
-                if let Some(content) = String::from_utf8(content.to_vec()).ok() {
-                    if let Some(project) = Project::load_content(content) {
-                        return Some(project);
-                    }
+/// Default number of seconds the labs in `lab_registry`'s catalog are cached for
+const LAB_REGISTRY_TTL: u64 = 60;
+
+/// Caches the labs in `lab_registry`'s catalog, so that `/labs` doesn't list the catalog on every request
+#[derive(Clone, Default)]
+struct CatalogCache {
+    labs: Arc<Mutex<Option<(Instant, Vec<String>)>>>,
+}
+
+impl CatalogCache {
+    /// Returns the labs in the registry's catalog, the catalog is only listed again once the cached labs are older than `ttl`
+    async fn labs(&self, lab_registry: &str, ttl: Duration) -> Vec<String> {
+        let cached = self.labs.lock().ok().and_then(|labs| labs.clone());
+        if let Some((listed_at, labs)) = cached {
+            if listed_at.elapsed() < ttl {
+                return labs;
+            }
+        }
+
+        match Registry::new().catalog(lab_registry).await {
+            Ok(labs) => {
+                let labs = labs.into_iter().map(|l| l.reference).collect::<Vec<_>>();
+                if let Ok(mut cached) = self.labs.lock() {
+                    *cached = Some((Instant::now(), labs.clone()));
                 }
+                labs
+            }
+            Err(err) => {
+                event!(Level::ERROR, "could not list labs in {lab_registry}, {err}");
+                vec![]
+            }
+        }
+    }
+}
+
+/// Broadcasts the name of each lab in `lab_dir` that changes
+#[derive(Clone, Default)]
+struct Reloads {
+    sender: Option<broadcast::Sender<String>>,
+    _hot_reload: Option<Arc<Mutex<HotReload>>>,
+}
+
+impl Reloads {
+    /// Watches every `.runmd` in `lab_dir`
+    fn watch(lab_dir: impl AsRef<str>) -> Self {
+        let lab_dir = match PathBuf::from(lab_dir.as_ref()).canonicalize() {
+            Ok(lab_dir) => lab_dir,
+            Err(err) => {
+                event!(Level::DEBUG, "not watching {}, {err}", lab_dir.as_ref());
+                return Self::default();
+            }
+        };
+
+        let (sender, _) = broadcast::channel(16);
+        let notify = sender.clone();
+        let root = lab_dir.clone();
+        match HotReload::watch(&lab_dir, move |Reload { path, .. }| {
+            let name = path
+                .parent()
+                .and_then(|p| p.strip_prefix(&root).ok())
+                .and_then(|p| p.to_str())
+                .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"));
+
+            if let Some(name) = name {
+                event!(Level::INFO, "reloaded lab {name}");
+                notify.send(name).ok();
+            }
+        }) {
+            Ok(hot_reload) => Self {
+                sender: Some(sender),
+                _hot_reload: Some(Arc::new(Mutex::new(hot_reload))),
+            },
+            Err(err) => {
+                event!(Level::ERROR, "could not watch {:?}, {err}", lab_dir);
+                Self::default()
+            }
+        }
+    }
+
+    /// Returns a receiver for the names of labs that change, if labs are being watched
+    fn subscribe(&self) -> Option<broadcast::Receiver<String>> {
+        self.sender.as_ref().map(|s| s.subscribe())
+    }
+}
+
+impl Lab {
+    /// Loads a project w/ the resolver, from an overlay, a file, or from the embedded `design/` folder
+    pub async fn get_project(resolver: &Resolver, project_src: impl AsRef<str>) -> Option<Project> {
+        if let Some(content) = resolver.get_string(project_src.as_ref()) {
+            if let Some(project) = Project::load_content(content) {
+                return Some(project);
             }
         }
 
@@ -58,21 +160,10 @@ impl Lab {
             }
         } 
         
-        if let Some(_lab) = Resources("design")
-            .read_binary::<Design>(
-                &dispatcher,
-                &format!("design/{name}/.runmd").as_str().to_string(),
-            )
-            .await
-        {
-            match String::from_utf8(_lab.to_vec()) {
-                Ok(content) => return content,
-                Err(err) => {
-                    event!(Level::ERROR, "error reading embedded lab {err}");
-                }
-            }
-        } 
-        
+        if let Some(content) = Resolver::from(dispatcher.as_ref()).get_string(format!("design/{name}/.runmd")) {
+            return content;
+        }
+
         if let Some(_lab) = dispatcher.as_ref().find_binary(&name) {
             event!(Level::TRACE, "found lab in graph, {name}");
             String::from_utf8(_lab).ok().unwrap_or_default()
@@ -96,23 +187,40 @@ impl Plugin<ThunkContext> for Lab {
             let mut tc = context.clone();
             async move {
                 if let Some(project_src) = tc.as_ref().find_text("project_src") {
-                    if let Some(project) = Self::get_project(project_src).await {
+                    let resolver = Resolver::from(tc.as_ref());
+                    if let Some(project) = Self::get_project(&resolver, &project_src).await {
                         let block_name = tc.block.block_name.to_string();
                         if let Some(address) = tc.as_ref().find_text("address") {
-                            let project =
-                                project.with_block(&tc.block.block_name, "app_host", |c| {
-                                    c.add_text_attr("address", &address);
-                                });
+                            let with_app_host = {
+                                let block_name = block_name.to_string();
+                                let address = address.to_string();
+                                move |project: Project| {
+                                    project.with_block(&block_name, "app_host", |c| {
+                                        c.add_text_attr("address", &address);
+                                    })
+                                }
+                            };
+                            let project = with_app_host(project);
 
                             let link = format!("http://{address}/{block_name}");
                             let log = format!("Starting lab on {link}");
 
+                            if tc.as_ref().is_enabled("dry_run").unwrap_or_default() {
+                                eprintln!("lab (dry_run): would start lab on {link}");
+                                for (name, _) in project.iter_block() {
+                                    eprintln!("  would load block {name}");
+                                }
+                                return Some(tc);
+                            }
+
                             tc.update_status_only(&log).await;
                             eprintln!("{log}");
 
                             let runtime = create_runtime(project);
                             let runtime_editor = RuntimeEditor::new(runtime);
-                            let mut extension = Host::from(runtime_editor);
+                            let mut extension = Host::from(runtime_editor)
+                                .watch(&project_src)
+                                .prepare_reloads(with_app_host);
 
                             tc.as_mut().add_bool_attr("proxy_dispatcher", true);
                             Runtime::start_with::<Host, Call>(
@@ -133,17 +241,28 @@ impl Plugin<ThunkContext> for Lab {
 
 impl WebApp for Lab {
     fn create(tc: &mut ThunkContext) -> Self {
-        Self(tc.clone())
+        let reloads = tc
+            .as_ref()
+            .find_text("lab_dir")
+            .filter(|_| tc.as_ref().is_enabled("hot_reload").unwrap_or(true))
+            .map(Reloads::watch)
+            .unwrap_or_default();
+
+        Self(tc.clone(), reloads, CatalogCache::default())
     }
 
     fn routes(&mut self) -> poem::Route {
         Route::new()
-            .nest("/.run", EmbeddedFilesEndpoint::<Design>::new())
+            .at("/.run/*path", get(resource.data(self.0.clone())))
             .at("/:lab_name", get(index))
             .at("/lab/:name", get(lab.data(self.0.clone())))
             .at("/lab/:name/status", get(lab_status.data(self.0.clone())))
-            .at("/labs", get(labs.data(self.0.clone())))
-            .at("/dispatch/:name", get(dispatch.data(self.0.clone())))
+            .at("/lab/:name/fix/:dep", post(lab_fix.data(self.0.clone())))
+            .at("/labs", get(labs.data(self.0.clone()).data(self.2.clone())))
+            .at(
+                "/dispatch/:name",
+                get(dispatch.data(self.0.clone()).data(self.1.clone())),
+            )
     }
 }
 
@@ -152,10 +271,28 @@ fn dispatch(
     Path(name): Path<String>,
     ws: WebSocket,
     dispatcher: Data<&ThunkContext>,
+    reloads: Data<&Reloads>,
 ) -> impl IntoResponse {
     let dispatcher = dispatcher.clone();
+    let reloads = reloads.subscribe();
     ws.on_upgrade(move |socket| async move {
-        let (_, mut stream) = socket.split();
+        let (mut sink, mut stream) = socket.split();
+
+        if let Some(mut reloads) = reloads {
+            tokio::spawn(async move {
+                loop {
+                    match reloads.recv().await {
+                        Ok(lab) => {
+                            if sink.send(Message::Text(format!("reloaded {lab}"))).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
 
         tokio::spawn(async move {
             while let Some(Ok(msg)) = stream.next().await {
@@ -179,59 +316,45 @@ async fn lab(Path(name): Path<String>, dispatcher: Data<&ThunkContext>) -> Strin
 struct LabStatus {
     overview: String,
     expectations: Vec<String>,
+    /// Routes that can be used to fix a failed expectation, ex. `/lab/azure/fix/az`
+    fixes: Vec<String>,
 }
 
 #[handler]
 async fn lab_status(Path(name): Path<String>, dispatcher: Data<&ThunkContext>) -> Json<LabStatus> {
     let content = Lab::resolve_lab_content(&dispatcher, &name).await;
     let graph = AttributeGraph::from(0);
-    let graph = graph.batch(content).ok().unwrap_or_default();
+    let graph = graph.batch(&content).ok().unwrap_or_default();
     let project = Project::from(graph);
     let mut status = LabStatus::default();
 
     event!(Level::DEBUG, "Looking for lab block for {name}");
     event!(Level::TRACE, "Project Content\n{:#?}", project);
-    if let Some(block) = project.find_block(name) {
+    if let Some(block) = project.find_block(&name) {
         if let Some(lab_block) = block.get_block("lab") {
             let overview = lab_block.find_text("overview").unwrap_or_default();
             status.overview = overview;
         }
     }
 
-    for (block_name, block) in project.iter_block() {
-        if let Some(mut expect) = block.get_block("expect") {
-            let mut deps = BTreeMap::<String, String>::default();
-
-            for (name, value) in expect.clone().find_symbol_values("which") {
-                if Expect::should_expect(name, "which") {
-                    if let Value::TextBuffer(dep) = value {
-                        deps.insert(dep, "ok".to_string());
-                    }
-                }
-            }
-
-            let mut dispatcher = dispatcher.clone();
-            *dispatcher.as_mut() = expect;
-            if let Some(task) = Expect::call_with_context(&mut dispatcher) {
-                match task.0.await {
-                    Ok(result) => {
-                        if let Some(error_context) = result.get_errors() {
-                            for (name, error) in error_context.errors() {
-                                if let Some(prev) = deps.insert(name.to_string(), error.to_string())
-                                {
-                                    eprintln!("{name} {prev} -> {error}");
-                                }
-                            }
-                        }
-                    }
-                    Err(_) => {}
+    for expectation in Check::run(Check::gather(vec![(name.to_string(), content)])).await {
+        let Expectation {
+            block_name,
+            dep,
+            error,
+            fix_script,
+            ..
+        } = expectation;
+
+        match error {
+            Some(error) => {
+                if fix_script.is_some() {
+                    status.fixes.push(format!("/lab/{name}/fix/{dep}"));
                 }
+                status.expectations.push(format!("{block_name} - {dep} {error}"));
             }
-
-            for (name, dep_status) in deps {
-                status
-                    .expectations
-                    .push(format!("{block_name} - {name} {dep_status}"));
+            None => {
+                status.expectations.push(format!("{block_name} - {dep} ok"));
             }
         }
     }
@@ -240,14 +363,72 @@ async fn lab_status(Path(name): Path<String>, dispatcher: Data<&ThunkContext>) -
 }
 
 #[handler]
-async fn labs(dispatcher: Data<&ThunkContext>) -> String {
-    let mut builtin = Design::labs();
-
-    if dispatcher.as_ref().is_enabled("skip_builtin").unwrap_or_default() {
-        builtin = builtin.iter()
-            .filter(|b| b.starts_with("portal"))
-            .map(|l| l.to_string())
-            .collect::<Vec<_>>();
+async fn lab_fix(
+    Path((name, dep)): Path<(String, String)>,
+    dispatcher: Data<&ThunkContext>,
+) -> Response {
+    if !dispatcher.as_ref().is_enabled("enable_lab_fix").unwrap_or_default() {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("fix scripts are disabled, set enable_lab_fix to run fix scripts from the portal");
+    }
+
+    let content = Lab::resolve_lab_content(&dispatcher, &name).await;
+    let work_dir = dispatcher
+        .as_ref()
+        .find_text("fix_work_dir")
+        .unwrap_or(FIX_WORK_DIR.to_string());
+    let fix_config = Check::fix_config(dispatcher.as_ref(), work_dir);
+
+    let mut fixed = vec![];
+    for expectation in Check::run(Check::gather(vec![(name, content)])).await {
+        if expectation.dep == dep {
+            event!(Level::DEBUG, "fixing {dep} for {}", expectation.block_name);
+            fixed.push(Check::fix(expectation, &fix_config).await);
+        }
+    }
+
+    Json(fixed).into_response()
+}
+
+/// Serves files under `design/` from an overlay or the embedded folder, paths w/ `..` are not found
+#[handler]
+fn resource(Path(path): Path<String>, dispatcher: Data<&ThunkContext>) -> Response {
+    let path = format!("design/{}", path.trim_start_matches('/'));
+
+    match Resolver::from(dispatcher.as_ref()).get_virtual(&path) {
+        Some((content, _)) => Response::builder()
+            .content_type(mime_guess::from_path(&path).first_or_octet_stream().as_ref())
+            .body(content),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[handler]
+async fn labs(dispatcher: Data<&ThunkContext>, catalog: Data<&CatalogCache>) -> String {
+    let lab_dir = dispatcher.as_ref().find_text("lab_dir");
+    let skip_builtin = dispatcher.as_ref().is_enabled("skip_builtin").unwrap_or_default();
+
+    // Discovering labs reads and parses every .runmd in lab_dir
+    let discovered = tokio::task::spawn_blocking(move || Design::discover(lab_dir.as_deref()))
+        .await
+        .unwrap_or_else(|err| {
+            event!(Level::ERROR, "could not discover labs, {err}");
+            vec![]
+        });
+
+    let mut builtin = vec![];
+    for lab in discovered {
+        if skip_builtin && lab.embedded && !lab.name.starts_with("portal") {
+            continue;
+        }
+
+        match lab.error {
+            Some(err) => {
+                event!(Level::ERROR, "skipping lab {} at {}, {err}", lab.name, lab.location);
+            }
+            None => builtin.push(lab.path),
+        }
     }
 
     let mut labs: Vec<String> = dispatcher
@@ -261,8 +442,14 @@ async fn labs(dispatcher: Data<&ThunkContext>) -> String {
 
     builtin.append(&mut labs);
 
-    if let Some(lab_dir) = dispatcher.as_ref().find_text("lab_dir") {
-        builtin.append(&mut Design::find_labs(lab_dir).await);
+    if let Some(lab_registry) = dispatcher.as_ref().find_text("lab_registry") {
+        let ttl = dispatcher
+            .as_ref()
+            .find_text("lab_registry_ttl")
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(LAB_REGISTRY_TTL);
+
+        builtin.extend(catalog.labs(&lab_registry, Duration::from_secs(ttl)).await);
     }
 
     builtin.join("\n")
@@ -349,6 +536,12 @@ fn index(Path(lab_name): Path<String>) -> Html<String> {
         app.ports.dispatchRunmd.subscribe(function (message) {{
             ws.send(message);
         }})
+
+        ws.onmessage = function (event) {{
+            if (event.data === "reloaded {lab_name}") {{
+                location.reload();
+            }}
+        }};
 	</script>
 </body>
 </html>
@@ -356,3 +549,30 @@ fn index(Path(lab_name): Path<String>) -> Html<String> {
     );
     Html(html)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::resource;
+    use lifec::plugins::ThunkContext;
+    use poem::{get, http::StatusCode, test::TestClient, EndpointExt, Route};
+
+    #[tokio::test]
+    async fn test_resource_rejects_parent_dir() {
+        let app = Route::new().at("/.run/*path", get(resource.data(ThunkContext::default())));
+        let client = TestClient::new(app);
+
+        client
+            .get("/.run/portal/.runmd")
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        for path in ["/.run/../Cargo.toml", "/.run/portal/../../Cargo.toml", "/.run/..%2FCargo.toml"] {
+            client
+                .get(path)
+                .send()
+                .await
+                .assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::install::Install;
+
+/// Name of the ledger file `Install` keeps in each work_dir
+const LEDGER_FILE_NAME: &str = ".install-ledger.json";
+
+/// Name of the lock file held while the ledger is being updated
+const LOCK_FILE_NAME: &str = ".install-ledger.lock";
+
+/// How long to wait for another install to release the ledger
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Locks older than this were left behind by a process that exited w/o releasing them
+const STALE_LOCK: Duration = Duration::from_secs(120);
+
+/// Record of every file `Install` has written to a work_dir
+///
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+/// Record of a single installed file
+///
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    /// Name of the block that installed the file
+    pub block_name: String,
+    /// The `file_src` the file was installed from
+    pub file_src: String,
+    /// Where `file_src` was resolved from, `overlay`, `embedded`, `filesystem`, `https`, `oci`, or `pattern` for `{src_dir}/{tool_name}` sources
+    pub source: String,
+    /// Absolute path of the installed file
+    pub file_dst: String,
+    /// Hex encoded sha256 of the installed content
+    pub sha256: String,
+    /// Seconds since the unix epoch when the file was installed
+    pub installed_at: u64,
+    /// Path of the backup of the file that was there before the first install, if any
+    pub backup: Option<String>,
+}
+
+impl Ledger {
+    /// Loads the ledger for a work_dir, returns an empty ledger if one does not exist
+    ///
+    /// Returns an error if the ledger exists but could not be read or parsed, so that it's never overwritten
+    pub async fn load(work_dir: impl AsRef<str>) -> Result<Self, String> {
+        let path = Self::path(work_dir);
+        match tokio::fs::read(&path).await {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|e| format!("could not parse install ledger {:?}, {e}", path)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Ledger::default()),
+            Err(err) => Err(format!("could not read install ledger {:?}, {err}", path)),
+        }
+    }
+
+    /// Returns the entry for an installed file, if the file was installed to the work_dir
+    pub fn entry(&self, file_dst: impl AsRef<Path>) -> Option<&LedgerEntry> {
+        let file_dst = Self::absolute(file_dst);
+        self.entries.iter().find(|e| Self::absolute(&e.file_dst) == file_dst)
+    }
+
+    /// Saves the ledger to the work_dir
+    pub async fn save(&self, work_dir: impl AsRef<str>) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        tokio::fs::create_dir_all(work_dir.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+        tokio::fs::write(Self::path(work_dir), content)
+            .await
+            .map_err(|e| format!("could not write install ledger, {e}"))
+    }
+
+    /// Records an installed file, replacing any previous entry for the same destination
+    ///
+    /// `file_dst` is recorded as an absolute path. The backup of the previous entry is always kept, since it's
+    /// the only copy of the file that was there before the first install. The ledger is locked while it's updated,
+    /// so concurrent installs to the same work_dir don't lose entries
+    pub async fn record(work_dir: impl AsRef<str>, mut entry: LedgerEntry) -> Result<(), String> {
+        let _lock = LedgerLock::acquire(work_dir.as_ref()).await?;
+        let mut ledger = Self::load(work_dir.as_ref()).await?;
+
+        entry.file_dst = Self::absolute(&entry.file_dst).to_string_lossy().to_string();
+        if let Some(index) = ledger
+            .entries
+            .iter()
+            .position(|e| Self::absolute(&e.file_dst) == Self::absolute(&entry.file_dst))
+        {
+            let previous = ledger.entries.remove(index);
+            entry.backup = previous.backup;
+        }
+
+        if entry.installed_at == 0 {
+            entry.installed_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+        }
+
+        ledger.entries.push(entry);
+        ledger.save(work_dir).await
+    }
+
+    /// Removes, or restores from backup, every file installed by the block,
+    ///
+    /// Files that were modified since they were installed are skipped unless `force` is set. Files that were already
+    /// removed are still restored from their backup. If `dry_run` is set, nothing is removed and the ledger is left as is.
+    /// Returns a line describing what happened to each file, or an error if any file was skipped
+    pub async fn uninstall(
+        work_dir: impl AsRef<str>,
+        block_name: impl AsRef<str>,
+        force: bool,
+        dry_run: bool,
+    ) -> Result<Vec<String>, String> {
+        let _lock = if dry_run {
+            None
+        } else {
+            Some(LedgerLock::acquire(work_dir.as_ref()).await?)
+        };
+        let mut ledger = Self::load(work_dir.as_ref()).await?;
+        let mut log = vec![];
+        let mut skipped = vec![];
+        let mut remaining = vec![];
+
+        for entry in ledger.entries.drain(..) {
+            if entry.block_name != block_name.as_ref() {
+                remaining.push(entry);
+                continue;
+            }
+
+            let LedgerEntry {
+                file_dst,
+                sha256,
+                backup,
+                ..
+            } = &entry;
+
+            let backup = backup.as_ref().filter(|b| PathBuf::from(b).exists());
+            match tokio::fs::read(file_dst).await {
+                Ok(content) if !force && Install::sha256(&content) != *sha256 => {
+                    skipped.push(format!("{file_dst} was modified since it was installed"));
+                    remaining.push(entry);
+                    continue;
+                }
+                Ok(_) => {}
+                // The backup is the only copy of the file that was there before the first install, so it's still restored
+                Err(_) if backup.is_some() => {}
+                Err(_) => {
+                    log.push(format!("{file_dst} was already removed"));
+                    continue;
+                }
+            }
+
+            if dry_run {
+                log.push(match backup {
+                    Some(backup) => format!("would restore {file_dst} from {backup}"),
+                    None => format!("would remove {file_dst}"),
+                });
+                remaining.push(entry);
+                continue;
+            }
+
+            let result = match backup {
+                Some(backup) => tokio::fs::rename(backup, file_dst)
+                    .await
+                    .map(|_| format!("restored {file_dst} from {backup}")),
+                None => tokio::fs::remove_file(file_dst)
+                    .await
+                    .map(|_| format!("removed {file_dst}")),
+            };
+
+            match result {
+                Ok(line) => log.push(line),
+                Err(err) => {
+                    skipped.push(format!("could not uninstall {file_dst}, {err}"));
+                    remaining.push(entry);
+                }
+            }
+        }
+
+        ledger.entries = remaining;
+        if !dry_run {
+            ledger.save(work_dir).await?;
+        }
+
+        if skipped.is_empty() {
+            Ok(log)
+        } else {
+            log.append(&mut skipped);
+            Err(format!("{}\nuse force to uninstall modified files", log.join("\n")))
+        }
+    }
+
+    /// Returns the absolute path of an installed file, relative paths are resolved against the current directory
+    fn absolute(path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().unwrap_or_default().join(path)
+        }
+    }
+
+    /// Returns the path of the ledger in the work_dir
+    fn path(work_dir: impl AsRef<str>) -> PathBuf {
+        PathBuf::from(work_dir.as_ref()).join(LEDGER_FILE_NAME)
+    }
+}
+
+/// Lock on a work_dir's ledger, released when dropped
+///
+struct LedgerLock {
+    path: PathBuf,
+}
+
+impl LedgerLock {
+    /// Creates the lock file in the work_dir, waiting up to `LOCK_TIMEOUT` for another install to release it
+    async fn acquire(work_dir: impl AsRef<str>) -> Result<Self, String> {
+        let path = PathBuf::from(work_dir.as_ref()).join(LOCK_FILE_NAME);
+        tokio::fs::create_dir_all(work_dir.as_ref())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let started = Instant::now();
+        loop {
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => return Ok(Self { path }),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = tokio::fs::metadata(&path)
+                        .await
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|m| m.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK);
+
+                    if stale {
+                        event!(Level::WARN, "removing stale install ledger lock {:?}", path);
+                        tokio::fs::remove_file(&path).await.ok();
+                    } else if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(format!("timed out waiting for install ledger lock {:?}", path));
+                    } else {
+                        tokio::time::sleep(Duration::from_millis(25)).await;
+                    }
+                }
+                Err(err) => return Err(format!("could not lock install ledger {:?}, {err}", path)),
+            }
+        }
+    }
+}
+
+impl Drop for LedgerLock {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ledger, LedgerEntry};
+    use crate::install::Install;
+
+    /// Returns an empty work_dir for a test
+    fn work_dir(test: &str) -> String {
+        let work_dir = std::env::temp_dir().join(format!("chiron-ledger-{}-{test}", std::process::id()));
+        std::fs::remove_dir_all(&work_dir).ok();
+        std::fs::create_dir_all(&work_dir).expect("should create work_dir");
+        work_dir.to_string_lossy().to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_record() {
+        let work_dir = work_dir("concurrent");
+
+        let records = (0..16).map(|i| {
+            let work_dir = work_dir.to_string();
+            tokio::spawn(async move {
+                Ledger::record(
+                    &work_dir,
+                    LedgerEntry {
+                        block_name: "test".to_string(),
+                        file_dst: format!("{work_dir}/{i}.sh"),
+                        ..Default::default()
+                    },
+                )
+                .await
+            })
+        });
+
+        for record in futures_util::future::join_all(records).await {
+            record.expect("should join").expect("should record");
+        }
+
+        let ledger = Ledger::load(&work_dir).await.expect("should load");
+        assert_eq!(ledger.entries.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_uninstall_restores_backup_of_removed_file() {
+        let work_dir = work_dir("removed");
+        let file_dst = format!("{work_dir}/acr-login.sh");
+        let backup = format!("{file_dst}.bak");
+        std::fs::write(&backup, "original").expect("should write backup");
+
+        Ledger::record(
+            &work_dir,
+            LedgerEntry {
+                block_name: "test".to_string(),
+                file_dst: file_dst.to_string(),
+                sha256: Install::sha256("installed"),
+                backup: Some(backup.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("should record");
+
+        let log = Ledger::uninstall(&work_dir, "test", false, false)
+            .await
+            .expect("should uninstall");
+        assert_eq!(log, vec![format!("restored {file_dst} from {backup}")]);
+        assert_eq!(std::fs::read_to_string(&file_dst).ok(), Some("original".to_string()));
+
+        let ledger = Ledger::load(&work_dir).await.expect("should load");
+        assert!(ledger.entries.is_empty());
+    }
+}
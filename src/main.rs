@@ -3,8 +3,8 @@ use imgui::Window;
 use lifec::{
     editor::{Call, Fix},
     plugins::{
-        Config, Expect, Missing, OpenDir, OpenFile, Println, Process, Project, Redirect, Remote,
-        Timer, WriteFile,
+        AsyncContext, Config, Expect, Missing, OpenDir, OpenFile, Plugin, Println, Project, Redirect,
+        Remote, ThunkContext, Timer, WriteFile,
     },
     *,
 };
@@ -25,6 +25,20 @@ use cloud_init::ReadMime;
 mod install;
 use install::Install;
 
+mod ledger;
+use ledger::Ledger;
+
+mod fetch;
+
+#[cfg(test)]
+mod stand_in;
+
+mod uninstall;
+use uninstall::Uninstall;
+
+mod process;
+use process::Process;
+
 mod host;
 use host::Host;
 
@@ -32,10 +46,37 @@ mod lab;
 use lab::Lab;
 
 mod design;
+use design::Design;
+
+mod check;
+use check::Check;
+
+mod runner;
+use runner::Runner;
+
+mod assert;
+use assert::Assert;
+
+mod harness;
+use harness::Harness;
 
 mod acr;
 use acr::Acr;
 
+mod watch;
+
+mod resources;
+use resources::Resolver;
+
+mod archive;
+use archive::Archive;
+
+mod registry;
+use registry::Registry;
+
+#[cfg(feature = "inspector")]
+mod inspector;
+
 #[derive(Debug, Parser)]
 #[clap(name = "chiron")]
 #[clap(about = "Developer tool, for building interactive scripts and labs.", long_about = None)]
@@ -52,6 +93,101 @@ enum Commands {
     Init,
     /// Starts the runtime by loading a project .runmd file and passing the names of each engine block to start.
     Start(Start),
+    /// Checks that every dependency expected by the project and labs is installed, exits non-zero if any are missing.
+    Doctor(Doctor),
+    /// Runs engines from a project .runmd file end-to-end w/o the gui, exits non-zero if any event fails.
+    Run(Run),
+    /// Runs a lab in a temporary directory and evaluates its assert blocks, writing the results as JUnit XML.
+    #[clap(arg_required_else_help = true)]
+    Test(Test),
+    /// Removes the files an install block wrote, restoring any files that were backed up.
+    #[clap(arg_required_else_help = true)]
+    Uninstall(UninstallArgs),
+    /// Lists the resources embedded in chiron, and where each one currently resolves from.
+    Resources(ResourcesArgs),
+    /// Packages labs as portable archives.
+    #[clap(arg_required_else_help = true)]
+    Lab(LabArgs),
+}
+
+#[derive(Debug, Args)]
+struct LabArgs {
+    #[clap(subcommand)]
+    command: LabCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum LabCommands {
+    /// Lists the embedded labs and the labs in --lab-dir, and any that fail to parse
+    List(List),
+    /// Exports a lab, and every resource it references, to a tar.gz archive
+    Export(Export),
+    /// Imports a lab archive into --lab-dir
+    Import(Import),
+    /// Packages a lab and pushes it to a registry as an OCI artifact
+    Push(Push),
+    /// Pulls a lab from a registry and imports it into --lab-dir
+    Pull(Pull),
+}
+
+#[derive(Debug, Args)]
+struct Push {
+    /// Path to a lab .runmd file, an embedded design/ path, or the name of a lab in --lab-dir
+    lab: String,
+    /// Reference to push the lab to, ex. localhost:5000/labs/azure:v1
+    reference: String,
+    /// Directory to search for labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+    /// Overview of the lab, Defaults to the first heading of the lab's .runmd
+    #[clap(long)]
+    overview: Option<String>,
+    /// Version of the lab, Defaults to the tag of the reference
+    #[clap(long)]
+    version: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct Pull {
+    /// Reference to pull the lab from, ex. localhost:5000/labs/azure:v1
+    reference: String,
+    /// Directory to import the lab into
+    #[clap(long, default_value = "labs")]
+    lab_dir: String,
+    /// Replaces the lab if it already exists in --lab-dir
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+struct List {
+    /// Directory to search for labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct Export {
+    /// Path to a lab .runmd file, an embedded design/ path, or the name of a lab in --lab-dir
+    lab: String,
+    /// Directory to search for labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+    /// Path to write the archive to, Defaults to {name}.lab.tar.gz
+    #[clap(long, short)]
+    output: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct Import {
+    /// Path to a lab archive
+    archive: String,
+    /// Directory to import the lab into
+    #[clap(long, default_value = "labs")]
+    lab_dir: String,
+    /// Replaces the lab if it already exists in --lab-dir
+    #[clap(long)]
+    force: bool,
 }
 
 #[derive(Debug, Args)]
@@ -59,10 +195,97 @@ struct Start {
     /// Path to a .runmd file, Defaults to .runmd in the current directory
     #[clap(long, short)]
     project_src: Option<String>,
+    /// Prints the files that would be written and the commands that would run, w/o any side effects
+    #[clap(long)]
+    dry_run: bool,
     /// Engine block names to start. The blocks must be defined in the .runmd project file.
     engines: Vec<String>,
 }
 
+#[derive(Debug, Args)]
+struct Run {
+    /// Path to a .runmd file, an embedded design/ path, or the name of a lab in --lab-dir. Defaults to .runmd in the current directory
+    #[clap(long, short)]
+    project_src: Option<String>,
+    /// Directory to search for labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+    /// Prints the files that would be written and the commands that would run, w/o any side effects
+    #[clap(long)]
+    dry_run: bool,
+    /// Engine block names to run in order. The blocks must be defined in the .runmd project file.
+    engines: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct Test {
+    /// Path to a lab .runmd file, an embedded design/ path, or the name of a lab in --lab-dir
+    lab: String,
+    /// Directory to search for labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+    /// Path to write the JUnit XML results to
+    #[clap(long, default_value = "junit.xml")]
+    junit: String,
+    /// Engine block names to run in order, if not set every engine in the lab is run
+    engines: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct UninstallArgs {
+    /// Name of the install block to uninstall
+    block_name: String,
+    /// Path to a .runmd file, Defaults to .runmd in the current directory
+    #[clap(long, short)]
+    project_src: Option<String>,
+    /// The work_dir the block installed to, Defaults to the work_dir of the block's install attributes
+    #[clap(long)]
+    work_dir: Option<String>,
+    /// Removes files even if they were modified since they were installed
+    #[clap(long)]
+    force: bool,
+    /// Prints the files that would be removed or restored, w/o any side effects
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+struct ResourcesArgs {
+    /// Only lists resources under this prefix, ex. lib/sh
+    #[clap(long)]
+    prefix: Option<String>,
+    /// Directory that shadows embedded resources, Defaults to CHIRON_OVERLAY, or the overlay_dir of the project's `chiron resources` block
+    #[clap(long)]
+    overlay_dir: Option<String>,
+    /// Path to a .runmd file, Defaults to .runmd in the current directory
+    #[clap(long, short)]
+    project_src: Option<String>,
+    #[clap(subcommand)]
+    command: Option<ResourcesCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum ResourcesCommands {
+    /// Shows which embedded resources are overridden by the overlay, and how they differ
+    Diff,
+}
+
+#[derive(Debug, Args)]
+struct Doctor {
+    /// Path to a .runmd file, Defaults to .runmd in the current directory
+    #[clap(long, short)]
+    project_src: Option<String>,
+    /// Directory to search for additional labs
+    #[clap(long)]
+    lab_dir: Option<String>,
+    /// Skips checking the labs embedded in chiron
+    #[clap(long)]
+    skip_builtin: bool,
+    /// Installs and runs the fix script for each failed check, and then checks again
+    #[clap(long)]
+    fix: bool,
+}
+
 fn main() {
     tracing_subscriber::fmt::Subscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
@@ -77,9 +300,15 @@ fn main() {
         } => {
             let Start {
                 project_src,
+                dry_run,
                 engines,
             } = start;
 
+            let watch_src = project_src
+                .clone()
+                .filter(|p| PathBuf::from(p).exists())
+                .unwrap_or(".runmd".to_string());
+
             let project = if let Some(project_src) = project_src {
                 let project_src_path = PathBuf::from(&project_src);
                 if project_src_path.exists() {
@@ -93,13 +322,271 @@ fn main() {
                 Project::runmd()
             };
 
-            if let Some(project) = project {
+            if let Some(mut project) = project {
+                if dry_run {
+                    project = enable_dry_run(project);
+                }
+
                 let runtime = create_runtime(project);
-                lifec::start(Host::from(runtime), engines);
+                let mut host = Host::from(runtime).watch(watch_src);
+                if dry_run {
+                    host = host.prepare_reloads(enable_dry_run);
+                }
+                lifec::start(host, engines);
             } else {
                 event!(Level::ERROR, "Did not find any project src");
             }
         }
+        Cli {
+            command: Some(Commands::Doctor(doctor)),
+        } => {
+            let Doctor {
+                project_src,
+                lab_dir,
+                skip_builtin,
+                fix,
+            } = doctor;
+
+            let tokio_runtime = tokio::runtime::Runtime::new().expect("should be able to create a tokio runtime");
+            if !tokio_runtime.block_on(Check::doctor(project_src, lab_dir, skip_builtin, fix)) {
+                std::process::exit(1);
+            }
+        }
+        Cli {
+            command: Some(Commands::Run(run)),
+        } => {
+            let Run {
+                project_src,
+                lab_dir,
+                dry_run,
+                engines,
+            } = run;
+
+            let project_src = project_src.unwrap_or(".runmd".to_string());
+            match Runner::load(&project_src, lab_dir)
+                .and_then(|source| Project::load_content(source.clone()).map(|project| (project, source)))
+            {
+                Some((mut project, source)) => {
+                    if dry_run {
+                        project = enable_dry_run(project);
+                    }
+
+                    if !Runner::new(project, source).run(engines) {
+                        std::process::exit(1);
+                    }
+                }
+                None => {
+                    event!(Level::ERROR, "Did not find any project src");
+                    eprintln!("could not load {project_src}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Cli {
+            command: Some(Commands::Test(test)),
+        } => {
+            let Test {
+                lab,
+                lab_dir,
+                junit,
+                engines,
+            } = test;
+
+            match Harness::load(&lab, lab_dir) {
+                Some(harness) => {
+                    if !harness.test(engines, junit) {
+                        std::process::exit(1);
+                    }
+                }
+                None => {
+                    eprintln!("could not load {lab}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Cli {
+            command: Some(Commands::Uninstall(uninstall)),
+        } => {
+            let UninstallArgs {
+                block_name,
+                project_src,
+                work_dir,
+                force,
+                dry_run,
+            } = uninstall;
+
+            let work_dir = work_dir.or_else(|| {
+                project_src
+                    .map(|p| Project::load_file(p))
+                    .unwrap_or_else(Project::runmd)
+                    .and_then(|p| p.find_block(&block_name))
+                    .and_then(|b| b.get_block(Install::symbol()))
+                    .and_then(|i| i.find_text("work_dir"))
+            });
+
+            match work_dir {
+                Some(work_dir) => {
+                    let tokio_runtime = tokio::runtime::Runtime::new().expect("should be able to create a tokio runtime");
+                    match tokio_runtime.block_on(Ledger::uninstall(&work_dir, &block_name, force, dry_run)) {
+                        Ok(log) => {
+                            for line in log {
+                                eprintln!("{line}");
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("{err}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                None => {
+                    eprintln!("could not find the work_dir for {block_name}, pass --work-dir");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Cli {
+            command: Some(Commands::Resources(resources)),
+        } => {
+            let ResourcesArgs {
+                prefix,
+                overlay_dir,
+                project_src,
+                command,
+            } = resources;
+
+            let mut resolver = project_src
+                .map(|p| Project::load_file(p))
+                .unwrap_or_else(Project::runmd)
+                .map(|p| Resolver::from_project(&p))
+                .unwrap_or_else(Resolver::new);
+
+            if let Some(overlay_dir) = overlay_dir {
+                resolver = resolver.overlay_dir(overlay_dir);
+            }
+
+            match command {
+                Some(ResourcesCommands::Diff) => {
+                    print!("{}", resolver.diff(prefix.as_deref()));
+                }
+                None => {
+                    for (path, origin) in resolver.list(prefix.as_deref()) {
+                        println!("{:<10} {path}", origin.as_str());
+                    }
+                }
+            }
+        }
+        Cli {
+            command: Some(Commands::Lab(LabArgs { command })),
+        } => match command {
+            LabCommands::List(List { lab_dir }) => {
+                let mut failed = false;
+                for lab in Design::discover(lab_dir.as_deref()) {
+                    let source = if lab.embedded { "embedded" } else { "lab_dir" };
+                    match lab.error {
+                        Some(err) => {
+                            failed = true;
+                            eprintln!("{:<10} {} ({}), {err}", "error", lab.name, lab.location);
+                        }
+                        None => {
+                            let title = lab.manifest.title.unwrap_or_default();
+                            println!("{source:<10} {:<24} {title}", lab.name);
+                        }
+                    }
+                }
+
+                if failed {
+                    std::process::exit(1);
+                }
+            }
+            LabCommands::Export(Export {
+                lab,
+                lab_dir,
+                output,
+            }) => {
+                let output = output.unwrap_or_else(|| {
+                    let name = lab
+                        .trim_end_matches(".runmd")
+                        .trim_end_matches('/')
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or("lab")
+                        .to_string();
+                    format!("{name}.lab.tar.gz")
+                });
+
+                match Archive::export(&lab, lab_dir, &output) {
+                    Ok(manifest) => {
+                        eprintln!("exported {} ({} files) to {output}", manifest.name, manifest.files.len());
+                    }
+                    Err(err) => {
+                        eprintln!("could not export {lab}, {err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            LabCommands::Import(Import {
+                archive,
+                lab_dir,
+                force,
+            }) => match Archive::import(&archive, &lab_dir, force) {
+                Ok(manifest) => {
+                    eprintln!("imported {} ({} files) to {lab_dir}/{}", manifest.name, manifest.files.len(), manifest.name);
+                }
+                Err(err) => {
+                    eprintln!("could not import {archive}, {err}");
+                    std::process::exit(1);
+                }
+            },
+            LabCommands::Push(Push {
+                lab,
+                reference,
+                lab_dir,
+                overview,
+                version,
+            }) => {
+                let tokio_runtime = tokio::runtime::Runtime::new().expect("should be able to create a tokio runtime");
+                let pushed = Archive::package(&lab, lab_dir).and_then(|(mut manifest, archive)| {
+                    if overview.is_some() {
+                        manifest.overview = overview;
+                    }
+
+                    tokio_runtime
+                        .block_on(Registry::new().push_lab(&reference, &manifest, archive, version))
+                        .map(|digest| (manifest, digest))
+                });
+
+                match pushed {
+                    Ok((manifest, digest)) => {
+                        eprintln!("pushed {} to {reference}@{digest}", manifest.name);
+                    }
+                    Err(err) => {
+                        eprintln!("could not push {lab}, {err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            LabCommands::Pull(Pull {
+                reference,
+                lab_dir,
+                force,
+            }) => {
+                let tokio_runtime = tokio::runtime::Runtime::new().expect("should be able to create a tokio runtime");
+                let pulled = tokio_runtime
+                    .block_on(Registry::new().pull_lab(&reference))
+                    .and_then(|archive| Archive::import_from(archive.as_slice(), &lab_dir, force));
+
+                match pulled {
+                    Ok(manifest) => {
+                        eprintln!("pulled {} ({} files) to {lab_dir}/{}", manifest.name, manifest.files.len(), manifest.name);
+                    }
+                    Err(err) => {
+                        eprintln!("could not pull {reference}, {err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        },
         Cli {
             command: Some(Commands::Init),
         } => {
@@ -112,7 +599,7 @@ fn main() {
                     "chiron",
                     Empty,
                     combine(
-                        Main(Host::from(runtime), NodeEditor::default()),
+                        Main(Host::from(runtime).watch(".runmd"), NodeEditor::default()),
                         Shell::default(),
                     ),
                 )
@@ -124,69 +611,151 @@ fn main() {
 }
 
 fn create_runtime(project: Project) -> Runtime {
-    let mut runtime = Runtime::new(project);
-
-    // --- lifec plugins ---
-    // -- Filesystem plugins
-    runtime.install::<Call, WriteFile>();
-    runtime.install::<Call, OpenFile>();
-    runtime.install::<Call, OpenDir>();
-    // -- Utility plugins
-    runtime.install::<Call, Println>();
-    runtime.install::<Call, Timer>();
-    // -- System plugins
-    runtime.install::<Call, Process>();
-    runtime.install::<Call, Remote>();
-    runtime.install::<Call, Expect>();
-    runtime.install::<Call, Runtime>();
-    runtime.install::<Call, Redirect>();
+    let mut runtime = Runtime::new(Resolver::apply(project));
+
+    for plugin in plugins() {
+        (plugin.install)(&mut runtime);
+    }
     runtime.install::<Fix, Missing>();
 
-    // --- lifec_poem plugins ---
-    // -- Hosting code
-    runtime.install::<Call, StaticFiles>();
-    runtime.install::<Call, AppHost<Lab>>();
-
-    // --- lifec_hyper plugins ---
-    // -- Client code
-    // this adds a "request" plugin to make https requests
-    runtime.install::<Call, HyperContext>();
-
-    // -- lifec_registry plugins --
-    runtime.install::<Call, Login>();
-    runtime.install::<Call, Authenticate>();
-    runtime.install::<Call, Resolve>();
-    runtime.install::<Call, MirrorHost<Acr>>();
-
-    // -- Cloud-init plugins --
-    runtime.install::<Call, MakeMime>();
-    runtime.install::<Call, ReadMime>();
-    runtime.install::<Call, Installer>();
-
-    // -- Cloud-init configs
-    runtime.add_config(Config("cloud_init", |tc| {
-        cloud_init::env(tc);
-    }));
-
-    runtime.add_config(Config("cloud_init_exit", |tc| {
-        tc.as_mut().add_text_attr("src_type", "exit");
-        cloud_init::env(tc);
-    }));
-
-    runtime.add_config(Config("cloud_init_enter", |tc| {
-        tc.as_mut().add_text_attr("src_type", "enter");
-        cloud_init::env(tc);
-    }));
-
-    // --- chiron plugins ---
-    runtime.install::<Call, Install>();
-    runtime.install::<Call, Lab>();
-
-    // common default configs
-    runtime.add_config(Config("empty", |_| {}));
+    for config in configs() {
+        runtime.add_config(config);
+    }
     runtime
 }
 
+/// Signature of `Plugin::call_with_context`
+type CallFn = fn(&mut ThunkContext) -> Option<AsyncContext>;
+
+/// A plugin installed w/ `Call` by the runtime,
+///
+/// Plugins that host a server are not called by the headless runner, since they do not return until they are cancelled
+struct RuntimePlugin {
+    symbol: &'static str,
+    call: CallFn,
+    install: fn(&mut Runtime),
+    hosts_server: bool,
+}
+
+/// Creates the `RuntimePlugin` for a plugin, add `hosts_server` if the plugin hosts a server
+macro_rules! plugin {
+    ($plugin:ty) => {
+        plugin!($plugin, false)
+    };
+    ($plugin:ty, hosts_server) => {
+        plugin!($plugin, true)
+    };
+    ($plugin:ty, $hosts_server:expr) => {
+        RuntimePlugin {
+            symbol: <$plugin as Plugin<ThunkContext>>::symbol(),
+            call: <$plugin as Plugin<ThunkContext>>::call_with_context,
+            install: |runtime| runtime.install::<Call, $plugin>(),
+            hosts_server: $hosts_server,
+        }
+    };
+}
+
+/// Plugins shared by the runtime and the headless runner
+fn plugins() -> Vec<RuntimePlugin> {
+    #[allow(unused_mut)]
+    let mut plugins = vec![
+        // --- lifec plugins ---
+        // -- Filesystem plugins
+        plugin!(WriteFile),
+        plugin!(OpenFile),
+        plugin!(OpenDir),
+        // -- Utility plugins
+        plugin!(Println),
+        plugin!(Timer),
+        // -- System plugins
+        plugin!(Process),
+        plugin!(Remote),
+        plugin!(Expect),
+        plugin!(Runtime, hosts_server),
+        plugin!(Redirect),
+        // --- lifec_poem plugins ---
+        // -- Hosting code
+        plugin!(StaticFiles, hosts_server),
+        plugin!(AppHost<Lab>, hosts_server),
+        // --- lifec_hyper plugins ---
+        // -- Client code
+        // this adds a "request" plugin to make https requests
+        plugin!(HyperContext),
+        // -- lifec_registry plugins --
+        plugin!(Login),
+        plugin!(Authenticate),
+        plugin!(Resolve),
+        plugin!(MirrorHost<Acr>, hosts_server),
+        // -- Cloud-init plugins --
+        plugin!(MakeMime),
+        plugin!(ReadMime),
+        plugin!(Installer),
+        // --- chiron plugins ---
+        plugin!(Install),
+        plugin!(Uninstall),
+        plugin!(Lab, hosts_server),
+        plugin!(Check),
+        plugin!(Assert),
+    ];
+
+    #[cfg(feature = "inspector")]
+    plugins.push(plugin!(inspector::Inspector, hosts_server));
+
+    plugins
+}
+
+/// Configs shared by the runtime and the headless runner
+fn configs() -> Vec<Config> {
+    vec![
+        // -- Cloud-init configs
+        Config("cloud_init", |tc| {
+            cloud_init::env(tc);
+        }),
+        Config("cloud_init_exit", |tc| {
+            tc.as_mut().add_text_attr("src_type", "exit");
+            cloud_init::env(tc);
+        }),
+        Config("cloud_init_enter", |tc| {
+            tc.as_mut().add_text_attr("src_type", "enter");
+            cloud_init::env(tc);
+        }),
+        // common default configs
+        Config("empty", |_| {}),
+    ]
+}
+
+/// Enables `dry_run` on every block handled by a plugin that supports it
+fn enable_dry_run(mut project: Project) -> Project {
+    let block_names = project
+        .iter_block()
+        .map(|(block_name, _)| block_name.to_string())
+        .collect::<Vec<_>>();
+
+    for block_name in block_names {
+        let block = match project.find_block(&block_name) {
+            Some(block) => block,
+            None => continue,
+        };
+
+        for symbol in [
+            Install::symbol(),
+            Uninstall::symbol(),
+            MakeMime::symbol(),
+            Installer::symbol(),
+            Lab::symbol(),
+            Process::symbol(),
+        ] {
+            if block.get_block(symbol).is_some() {
+                project = project.with_block(&block_name, symbol, |c| {
+                    c.add_bool_attr("dry_run", true);
+                });
+            }
+        }
+    }
+
+    project
+}
+
 struct Main(Host, NodeEditor);
 
 impl Extension for Main {
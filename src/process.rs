@@ -0,0 +1,34 @@
+use lifec::plugins::{AsyncContext, Plugin, ThunkContext};
+use lifec::{Component, DenseVecStorage};
+
+/// Runs `command` w/ lifec's `process` plugin, unless `dry_run` is enabled
+///
+/// In a dry run the command is only printed, so that `start --dry-run` and `run --dry-run` do not start any processes
+///
+#[derive(Component, Default)]
+#[storage(DenseVecStorage)]
+pub struct Process;
+
+impl Plugin<ThunkContext> for Process {
+    fn symbol() -> &'static str {
+        lifec::plugins::Process::symbol()
+    }
+
+    fn description() -> &'static str {
+        lifec::plugins::Process::description()
+    }
+
+    fn call_with_context(context: &mut ThunkContext) -> Option<AsyncContext> {
+        if !context.as_ref().is_enabled("dry_run").unwrap_or_default() {
+            return lifec::plugins::Process::call_with_context(context);
+        }
+
+        let command = context.as_ref().find_text("command").unwrap_or_default();
+        eprintln!("process (dry_run): would run {command}");
+
+        context.clone().task(|_| {
+            let tc = context.clone();
+            async move { Some(tc) }
+        })
+    }
+}
@@ -0,0 +1,673 @@
+use std::collections::BTreeMap;
+
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{event, Level};
+
+use crate::{
+    archive::Manifest,
+    fetch::{Fetch, RemoteSource, OCI_TITLE_ANNOTATION},
+    install::Install,
+};
+
+/// Artifact type of a lab pushed to a registry
+pub const LAB_ARTIFACT_TYPE: &str = "application/vnd.chiron.lab.v1";
+
+/// Media type of the layer containing the lab archive
+const LAB_LAYER_MEDIA_TYPE: &str = "application/vnd.chiron.lab.layer.v1.tar+gzip";
+
+/// Media type of the manifest a lab is pushed w/
+const OCI_MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// Media type, digest and content of the empty config descriptor, ex. `{}`
+const OCI_EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const OCI_EMPTY_DIGEST: &str = "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a";
+const OCI_EMPTY_CONTENT: &[u8] = b"{}";
+
+/// Annotations set on the manifest of a lab
+const OCI_DESCRIPTION_ANNOTATION: &str = "org.opencontainers.image.description";
+const OCI_VERSION_ANNOTATION: &str = "org.opencontainers.image.version";
+
+/// Env vars w/ the credentials used for registries that require them
+const USERNAME_ENV: &str = "CHIRON_REGISTRY_USERNAME";
+const PASSWORD_ENV: &str = "CHIRON_REGISTRY_PASSWORD";
+
+/// Env var w/ a comma separated list of token realm hosts credentials can also be sent to, ex. `auth.docker.io`
+const TRUSTED_REALMS_ENV: &str = "CHIRON_REGISTRY_TRUSTED_REALMS";
+
+/// Returns the registry credentials from `CHIRON_REGISTRY_USERNAME` and `CHIRON_REGISTRY_PASSWORD`, if both are set
+pub fn credentials() -> Option<(String, String)> {
+    match (std::env::var(USERNAME_ENV), std::env::var(PASSWORD_ENV)) {
+        (Ok(username), Ok(password)) => Some((username, password)),
+        _ => None,
+    }
+}
+
+/// Returns the token realm hosts from `CHIRON_REGISTRY_TRUSTED_REALMS`, ex. `auth.docker.io,localhost:5001`
+pub fn trusted_realms() -> Vec<String> {
+    std::env::var(TRUSTED_REALMS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .map(|r| r.trim().to_lowercase())
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+/// Authorization for a registry request
+///
+pub enum Auth {
+    Bearer(String),
+    Basic(String, String),
+}
+
+impl Auth {
+    /// Adds the authorization to a request
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::Basic(username, password) => request.basic_auth(username, Some(password)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Token {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Handles a `WWW-Authenticate` challenge for a request to `url`,
+///
+/// For a `Bearer realm="...",service="...",scope="..."` challenge a token is requested, w/ credentials if set and the realm
+/// is on the same host as `url` or listed in `trusted_realms`, otherwise anonymously. Registries such as Docker Hub issue
+/// tokens from a different host, ex. `auth.docker.io`, so that host must be trusted explicitly. For a `Basic` challenge,
+/// the credentials are used directly
+///
+/// lifec_registry's `Login` and `Authenticate` plugins are engine events for the ACR mirror, they need a `ThunkContext` and
+/// an ACR login, so labs and remote sources are authorized w/ this token flow instead, which works w/ any distribution registry
+pub async fn authorize(
+    client: &reqwest::Client,
+    url: &str,
+    challenge: Option<String>,
+    credentials: Option<&(String, String)>,
+    trusted_realms: &[String],
+) -> Option<Auth> {
+    let challenge = challenge?;
+
+    if challenge.starts_with("Basic") {
+        return credentials.map(|(username, password)| Auth::Basic(username.to_string(), password.to_string()));
+    }
+
+    let params = challenge.strip_prefix("Bearer ")?;
+
+    let mut realm = None;
+    let mut query = vec![];
+    for param in params.split(',') {
+        if let Some((key, value)) = param.trim().split_once('=') {
+            let value = value.trim_matches('"').to_string();
+            match key {
+                "realm" => realm = Some(value),
+                _ => query.push((key.to_string(), value)),
+            }
+        }
+    }
+
+    let realm = reqwest::Url::parse(&realm?).ok()?;
+    let same_host = reqwest::Url::parse(url)
+        .map(|url| {
+            url.host_str() == realm.host_str() && url.port_or_known_default() == realm.port_or_known_default()
+        })
+        .unwrap_or_default();
+    let trusted = realm.host_str().is_some_and(|host| {
+        let host_port = realm.port().map(|port| format!("{host}:{port}"));
+        trusted_realms
+            .iter()
+            .any(|r| *r == host || Some(r) == host_port.as_ref())
+    });
+
+    let mut request = client.get(realm.clone()).query(&query);
+    match credentials {
+        Some((username, password)) if same_host || trusted => {
+            request = request.basic_auth(username, Some(password));
+        }
+        Some(_) => {
+            event!(
+                Level::WARN,
+                "not sending credentials to {realm}, it is not on the same host as {url}, add it to {TRUSTED_REALMS_ENV} to trust it"
+            );
+        }
+        None => {}
+    }
+
+    let response = request.send().await.ok()?;
+    let Token {
+        token,
+        access_token,
+    } = response.json::<Token>().await.ok()?;
+    token.or(access_token).map(Auth::Bearer)
+}
+
+/// A lab found in a registry
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistryLab {
+    /// Reference of the lab, ex. `localhost:5000/labs/azure:v1`
+    pub reference: String,
+    /// Title annotation of the lab
+    pub title: Option<String>,
+    /// Overview annotation of the lab
+    pub overview: Option<String>,
+    /// Version annotation of the lab
+    pub version: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LabManifest {
+    artifact_type: Option<String>,
+    config: Option<Descriptor>,
+    layers: Vec<Descriptor>,
+    annotations: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: Option<String>,
+    digest: String,
+}
+
+#[derive(Deserialize)]
+struct Catalog {
+    repositories: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    tags: Option<Vec<String>>,
+}
+
+/// Pushes and pulls labs packaged by `Archive` as OCI artifacts
+///
+/// Labs are pushed as an OCI image manifest w/ the `application/vnd.chiron.lab.v1` artifactType, an empty config, and
+/// a single layer w/ the lab archive. The manifest is annotated w/ the title, overview and version of the lab.
+/// Credentials are read from `CHIRON_REGISTRY_USERNAME` and `CHIRON_REGISTRY_PASSWORD`, and token realms on another host
+/// are trusted w/ `CHIRON_REGISTRY_TRUSTED_REALMS`
+pub struct Registry {
+    client: reqwest::Client,
+    credentials: Option<(String, String)>,
+    trusted_realms: Vec<String>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    /// Creates a new registry client w/ credentials and trusted realms from the env, if set
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            credentials: credentials(),
+            trusted_realms: trusted_realms(),
+        }
+    }
+
+    /// Pushes a packaged lab to `reference`, ex. `localhost:5000/labs/azure:v1`, returns the digest of the manifest
+    ///
+    /// If `version` is not set, the tag of the reference is used
+    pub async fn push_lab(
+        &self,
+        reference: impl AsRef<str>,
+        manifest: &Manifest,
+        archive: Vec<u8>,
+        version: Option<String>,
+    ) -> Result<String, String> {
+        let (registry, repository, tag) = Self::parse(reference.as_ref())?;
+        let base = Fetch::registry_url(&registry);
+
+        let layer_digest = format!("sha256:{}", Install::sha256(&archive));
+        let layer_size = archive.len();
+        self.push_blob(&base, &repository, OCI_EMPTY_DIGEST, OCI_EMPTY_CONTENT.to_vec())
+            .await?;
+        self.push_blob(&base, &repository, &layer_digest, archive).await?;
+
+        let mut annotations = BTreeMap::new();
+        annotations.insert(OCI_TITLE_ANNOTATION, manifest.name.to_string());
+        annotations.insert(OCI_VERSION_ANNOTATION, version.unwrap_or_else(|| tag.to_string()));
+        if let Some(overview) = manifest.overview.as_ref() {
+            annotations.insert(OCI_DESCRIPTION_ANNOTATION, overview.to_string());
+        }
+
+        let mut layer_annotations = BTreeMap::new();
+        layer_annotations.insert(OCI_TITLE_ANNOTATION, format!("{}.lab.tar.gz", manifest.name));
+
+        let oci_manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_MANIFEST_MEDIA_TYPE,
+            "artifactType": LAB_ARTIFACT_TYPE,
+            "config": {
+                "mediaType": OCI_EMPTY_MEDIA_TYPE,
+                "digest": OCI_EMPTY_DIGEST,
+                "size": OCI_EMPTY_CONTENT.len(),
+            },
+            "layers": [{
+                "mediaType": LAB_LAYER_MEDIA_TYPE,
+                "digest": layer_digest,
+                "size": layer_size,
+                "annotations": layer_annotations,
+            }],
+            "annotations": annotations,
+        });
+        let oci_manifest = serde_json::to_vec(&oci_manifest).map_err(|e| e.to_string())?;
+
+        let url = format!("{base}/v2/{repository}/manifests/{tag}");
+        let response = self
+            .send(|| {
+                self.client
+                    .put(&url)
+                    .header(header::CONTENT_TYPE, OCI_MANIFEST_MEDIA_TYPE)
+                    .body(oci_manifest.clone())
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(format!("could not push manifest to {url}, {}", response.status()));
+        }
+
+        Ok(format!("sha256:{}", Install::sha256(&oci_manifest)))
+    }
+
+    /// Pulls a lab from `reference`, returns the content of the lab archive
+    pub async fn pull_lab(&self, reference: impl AsRef<str>) -> Result<Vec<u8>, String> {
+        let (registry, repository, tag) = Self::parse(reference.as_ref())?;
+        let base = Fetch::registry_url(&registry);
+
+        let manifest = self.manifest(&base, &repository, &tag).await?;
+        if !Self::is_lab(&manifest) {
+            return Err(format!("{} is not a chiron lab", reference.as_ref()));
+        }
+
+        let layer = manifest
+            .layers
+            .iter()
+            .find(|l| l.media_type.as_deref() == Some(LAB_LAYER_MEDIA_TYPE))
+            .ok_or(format!("{} does not contain a lab archive", reference.as_ref()))?;
+
+        let url = format!("{base}/v2/{repository}/blobs/{}", layer.digest);
+        let archive = self.get(&url, None).await?;
+
+        if !layer
+            .digest
+            .trim_start_matches("sha256:")
+            .eq_ignore_ascii_case(&Install::sha256(&archive))
+        {
+            return Err(format!("blob digest mismatch for {}", layer.digest));
+        }
+
+        Ok(archive)
+    }
+
+    /// Lists every lab in the registry's catalog
+    pub async fn catalog(&self, registry: impl AsRef<str>) -> Result<Vec<RegistryLab>, String> {
+        let registry = registry.as_ref().trim_start_matches("oci://").trim_end_matches('/');
+        let base = Fetch::registry_url(registry);
+
+        let catalog = self.get(&format!("{base}/v2/_catalog"), None).await?;
+        let repositories = serde_json::from_slice::<Catalog>(&catalog)
+            .map_err(|e| format!("could not parse catalog, {e}"))?
+            .repositories
+            .unwrap_or_default();
+
+        let mut labs = vec![];
+        for repository in repositories {
+            let tags = match self
+                .get(&format!("{base}/v2/{repository}/tags/list"), None)
+                .await
+                .and_then(|t| serde_json::from_slice::<Tags>(&t).map_err(|e| e.to_string()))
+            {
+                Ok(tags) => tags.tags.unwrap_or_default(),
+                Err(err) => {
+                    event!(Level::DEBUG, "skipping {repository}, {err}");
+                    continue;
+                }
+            };
+
+            for tag in tags {
+                match self.manifest(&base, &repository, &tag).await {
+                    Ok(manifest) if Self::is_lab(&manifest) => {
+                        let annotations = manifest.annotations.unwrap_or_default();
+                        labs.push(RegistryLab {
+                            reference: format!("{registry}/{repository}:{tag}"),
+                            title: annotations.get(OCI_TITLE_ANNOTATION).cloned(),
+                            overview: annotations.get(OCI_DESCRIPTION_ANNOTATION).cloned(),
+                            version: annotations.get(OCI_VERSION_ANNOTATION).cloned(),
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        event!(Level::DEBUG, "skipping {repository}:{tag}, {err}");
+                    }
+                }
+            }
+        }
+
+        Ok(labs)
+    }
+
+    /// Uploads a blob, unless the repository already has it
+    async fn push_blob(
+        &self,
+        base: &str,
+        repository: &str,
+        digest: &str,
+        content: Vec<u8>,
+    ) -> Result<(), String> {
+        let url = format!("{base}/v2/{repository}/blobs/{digest}");
+        if self
+            .send(|| self.client.head(&url))
+            .await
+            .map(|r| r.status().is_success())
+            .unwrap_or_default()
+        {
+            event!(Level::DEBUG, "{digest} already exists in {repository}");
+            return Ok(());
+        }
+
+        let url = format!("{base}/v2/{repository}/blobs/uploads/");
+        let response = self.send(|| self.client.post(&url)).await?;
+        if response.status() != StatusCode::ACCEPTED {
+            return Err(format!("could not start upload to {url}, {}", response.status()));
+        }
+
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .ok_or(format!("registry did not return an upload location for {url}"))?;
+
+        let location = if location.starts_with('/') {
+            format!("{base}{location}")
+        } else {
+            location.to_string()
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{location}{separator}digest={digest}");
+
+        let response = self
+            .send(|| {
+                self.client
+                    .put(&url)
+                    .header(header::CONTENT_TYPE, "application/octet-stream")
+                    .body(content.clone())
+            })
+            .await?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(format!("could not upload {digest}, {}", response.status()));
+        }
+
+        Ok(())
+    }
+
+    /// Gets and parses the manifest for a tag
+    async fn manifest(&self, base: &str, repository: &str, tag: &str) -> Result<LabManifest, String> {
+        let url = format!("{base}/v2/{repository}/manifests/{tag}");
+        let manifest = self.get(&url, Some(OCI_MANIFEST_MEDIA_TYPE)).await?;
+
+        if tag.starts_with("sha256:")
+            && !tag.trim_start_matches("sha256:").eq_ignore_ascii_case(&Install::sha256(&manifest))
+        {
+            return Err(format!("manifest digest mismatch for {repository}@{tag}"));
+        }
+
+        serde_json::from_slice::<LabManifest>(&manifest).map_err(|e| format!("could not parse manifest, {e}"))
+    }
+
+    /// Returns true if the manifest is a chiron lab
+    ///
+    /// Registries that drop artifactType are handled by checking the layer media type
+    fn is_lab(manifest: &LabManifest) -> bool {
+        manifest.artifact_type.as_deref() == Some(LAB_ARTIFACT_TYPE)
+            || (manifest
+                .config
+                .as_ref()
+                .and_then(|c| c.media_type.as_deref())
+                == Some(OCI_EMPTY_MEDIA_TYPE)
+                && manifest
+                    .layers
+                    .iter()
+                    .any(|l| l.media_type.as_deref() == Some(LAB_LAYER_MEDIA_TYPE)))
+    }
+
+    /// Sends a GET request, returns the body if the request succeeded
+    async fn get(&self, url: &str, accept: Option<&str>) -> Result<Vec<u8>, String> {
+        let response = self
+            .send(|| {
+                let request = self.client.get(url);
+                match accept {
+                    Some(accept) => request.header(header::ACCEPT, accept),
+                    None => request,
+                }
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(format!("could not get {url}, {}", response.status()));
+        }
+
+        response
+            .bytes()
+            .await
+            .map(|b| b.to_vec())
+            .map_err(|e| format!("could not read {url}, {e}"))
+    }
+
+    /// Sends a request, if the registry responds w/ a challenge the request is sent again w/ authorization
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response, String> {
+        let response = request().send().await.map_err(|e| e.to_string())?;
+
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_string());
+
+        let url = response.url().to_string();
+        match authorize(&self.client, &url, challenge, self.credentials.as_ref(), &self.trusted_realms).await {
+            Some(auth) => auth.apply(request()).send().await.map_err(|e| e.to_string()),
+            None => Ok(response),
+        }
+    }
+
+    /// Parses a reference into the registry, repository and tag
+    fn parse(reference: &str) -> Result<(String, String, String), String> {
+        let reference = if reference.starts_with("oci://") {
+            reference.to_string()
+        } else {
+            format!("oci://{reference}")
+        };
+
+        match RemoteSource::parse(&reference) {
+            Some(RemoteSource::Oci {
+                registry,
+                repository,
+                reference,
+            }) => Ok((registry, repository, reference)),
+            _ => Err(format!("{reference} is not a valid reference, ex. localhost:5000/labs/azure:v1")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+    use crate::{archive::Manifest, stand_in::StandIn};
+
+    const ARCHIVE: &[u8] = b"lab archive";
+
+    /// Returns a registry client w/ credentials
+    fn registry() -> Registry {
+        Registry {
+            client: reqwest::Client::new(),
+            credentials: Some(("user".to_string(), "password".to_string())),
+            trusted_realms: vec![],
+        }
+    }
+
+    fn manifest(name: &str) -> Manifest {
+        Manifest {
+            name: name.to_string(),
+            overview: Some(format!("{name} development")),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_pull_lab() {
+        let stand_in = StandIn::start().await;
+        let reference = format!("{}/labs/azure:v1", stand_in.host);
+
+        let digest = registry()
+            .push_lab(&reference, &manifest("azure"), ARCHIVE.to_vec(), None)
+            .await
+            .expect("should push");
+        assert!(stand_in.has_manifest("labs/azure", "v1"));
+        assert!(stand_in.has_manifest("labs/azure", &digest));
+
+        let archive = registry().pull_lab(&reference).await.expect("should pull");
+        assert_eq!(archive, ARCHIVE);
+
+        // Blobs the repository already has are not uploaded again
+        let uploads = || {
+            stand_in
+                .requests()
+                .iter()
+                .filter(|r| r.starts_with("POST"))
+                .count()
+        };
+        assert_eq!(uploads(), 2);
+        registry()
+            .push_lab(&reference, &manifest("azure"), ARCHIVE.to_vec(), None)
+            .await
+            .expect("should push");
+        assert_eq!(uploads(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pull_not_a_lab() {
+        let stand_in = StandIn::start().await;
+        stand_in.add_artifact("scripts/fix", "v1", "fix.sh", b"echo fix");
+
+        let err = registry()
+            .pull_lab(format!("{}/scripts/fix:v1", stand_in.host))
+            .await
+            .expect_err("should not pull an artifact that is not a lab");
+        assert!(err.contains("is not a chiron lab"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_catalog() {
+        let stand_in = StandIn::start().await;
+        stand_in.add_artifact("scripts/fix", "v1", "fix.sh", b"echo fix");
+        for (name, tag) in [("azure", "v1"), ("azure", "v2"), ("dev_box", "latest")] {
+            registry()
+                .push_lab(
+                    format!("{}/labs/{name}:{tag}", stand_in.host),
+                    &manifest(name),
+                    ARCHIVE.to_vec(),
+                    Some(format!("{tag}.0")),
+                )
+                .await
+                .expect("should push");
+        }
+
+        let labs = registry().catalog(&stand_in.host).await.expect("should list");
+        let mut references = labs.iter().map(|l| l.reference.to_string()).collect::<Vec<_>>();
+        references.sort();
+        assert_eq!(
+            references,
+            vec![
+                format!("{}/labs/azure:v1", stand_in.host),
+                format!("{}/labs/azure:v2", stand_in.host),
+                format!("{}/labs/dev_box:latest", stand_in.host),
+            ]
+        );
+
+        let dev_box = labs
+            .iter()
+            .find(|l| l.reference.ends_with("dev_box:latest"))
+            .expect("should list dev_box");
+        assert_eq!(dev_box.title.as_deref(), Some("dev_box"));
+        assert_eq!(dev_box.overview.as_deref(), Some("dev_box development"));
+        assert_eq!(dev_box.version.as_deref(), Some("latest.0"));
+    }
+
+    #[tokio::test]
+    async fn test_token_auth() {
+        let stand_in = StandIn::start().await;
+        stand_in.enable_token_auth();
+        let reference = format!("{}/labs/azure:v1", stand_in.host);
+
+        registry()
+            .push_lab(&reference, &manifest("azure"), ARCHIVE.to_vec(), None)
+            .await
+            .expect("should push w/ a token");
+        let archive = registry().pull_lab(&reference).await.expect("should pull w/ a token");
+        assert_eq!(archive, ARCHIVE);
+
+        let token_requests = stand_in.authorizations("/token");
+        assert!(!token_requests.is_empty());
+        assert!(token_requests
+            .iter()
+            .all(|a| a.as_deref().map(|a| a.starts_with("Basic ")).unwrap_or_default()));
+    }
+
+    #[tokio::test]
+    async fn test_credentials_not_sent_to_other_realm() {
+        let stand_in = StandIn::start().await;
+        let realm = StandIn::start().await;
+        stand_in.enable_token_auth();
+        stand_in.set_realm_host(&realm.host);
+        let reference = format!("{}/labs/azure:v1", stand_in.host);
+
+        registry()
+            .push_lab(&reference, &manifest("azure"), ARCHIVE.to_vec(), None)
+            .await
+            .expect("should push w/ an anonymous token");
+
+        let token_requests = realm.authorizations("/token");
+        assert!(!token_requests.is_empty());
+        assert!(token_requests.iter().all(|a| a.is_none()));
+        assert!(stand_in.authorizations("/token").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_credentials_sent_to_trusted_realm() {
+        let stand_in = StandIn::start().await;
+        let realm = StandIn::start().await;
+        stand_in.enable_token_auth();
+        stand_in.set_realm_host(&realm.host);
+        let reference = format!("{}/labs/azure:v1", stand_in.host);
+
+        let registry = Registry {
+            trusted_realms: vec![realm.host.to_string()],
+            ..registry()
+        };
+        registry
+            .push_lab(&reference, &manifest("azure"), ARCHIVE.to_vec(), None)
+            .await
+            .expect("should push w/ a token from the trusted realm");
+
+        let token_requests = realm.authorizations("/token");
+        assert!(!token_requests.is_empty());
+        assert!(token_requests.iter().all(|a| a.is_some()));
+    }
+}
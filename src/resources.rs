@@ -0,0 +1,387 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::{Component, PathBuf},
+};
+
+use lifec::{plugins::Project, AttributeGraph};
+use rust_embed::RustEmbed;
+use similar::TextDiff;
+
+use crate::{cloud_init::UserData, design::Design};
+
+#[derive(RustEmbed)]
+#[folder = "lib/sh"]
+pub struct Shell;
+
+#[derive(RustEmbed)]
+#[folder = "lib/elm"]
+pub struct Elm;
+
+/// Virtual prefixes that map to embedded folders
+pub const PREFIXES: [&str; 4] = ["lib/sh", "lib/cloud_init", "lib/elm", "design"];
+
+/// Env var that sets the overlay directory
+pub const OVERLAY_ENV: &str = "CHIRON_OVERLAY";
+
+/// Project block that sets project-wide resource settings, ex.
+///
+/// ````md
+/// ``` chiron resources
+/// add overlay_dir .text .chiron/overlay
+/// ```
+/// ````
+pub const PROJECT_BLOCK: (&str, &str) = ("chiron", "resources");
+
+/// Where a resource was resolved from
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    /// Found in an overlay directory
+    Overlay,
+    /// Found at the path relative to the current directory
+    Filesystem,
+    /// Found in the folders embedded in chiron
+    Embedded,
+}
+
+impl Origin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Origin::Overlay => "overlay",
+            Origin::Filesystem => "filesystem",
+            Origin::Embedded => "embedded",
+        }
+    }
+}
+
+/// Resolves virtual paths, ex. `lib/sh/acr-login.sh`, to content
+///
+/// Paths are resolved in order from,
+/// 1) the overlay directory for the path's prefix, if one is set
+/// 2) the overlay directory, which mirrors the prefixes, ex. `{overlay_dir}/lib/sh/acr-login.sh`
+/// 3) the path on disk, relative to the current directory
+/// 4) the folder embedded in chiron for the path's prefix
+///
+/// Prefix overlays are read from `{prefix}_overlay` text attributes, ex. `lib_sh_overlay`. The overlay directory
+/// is read from the `overlay_dir` text attribute, or from the `CHIRON_OVERLAY` env var
+///
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    /// Overlay directories by prefix
+    overlays: BTreeMap<String, PathBuf>,
+    /// Overlay directory that shadows every prefix
+    overlay_dir: Option<PathBuf>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&AttributeGraph> for Resolver {
+    fn from(graph: &AttributeGraph) -> Self {
+        let mut resolver = Resolver::new();
+        if let Some(overlay_dir) = graph.find_text("overlay_dir") {
+            resolver = resolver.overlay_dir(overlay_dir);
+        }
+
+        for prefix in PREFIXES {
+            if let Some(dir) = graph.find_text(format!("{}_overlay", prefix.replace('/', "_"))) {
+                resolver = resolver.overlay(prefix, dir);
+            }
+        }
+        resolver
+    }
+}
+
+impl Resolver {
+    /// Creates a new resolver, w/ the overlay directory from the `CHIRON_OVERLAY` env var if set
+    pub fn new() -> Self {
+        let resolver = Self {
+            overlays: BTreeMap::new(),
+            overlay_dir: None,
+        };
+
+        match std::env::var(OVERLAY_ENV) {
+            Ok(overlay_dir) if !overlay_dir.is_empty() => resolver.overlay_dir(overlay_dir),
+            _ => resolver,
+        }
+    }
+
+    /// Creates a new resolver w/ the settings from the project's `chiron resources` block
+    pub fn from_project(project: &Project) -> Self {
+        let (name, symbol) = PROJECT_BLOCK;
+        project
+            .find_block(name)
+            .and_then(|b| b.get_block(symbol))
+            .map(|graph| Self::from(&graph))
+            .unwrap_or_else(Self::new)
+    }
+
+    /// Adds the project's `overlay_dir` to every block that resolves resources, unless the block sets its own
+    pub fn apply(project: Project) -> Project {
+        let (name, symbol) = PROJECT_BLOCK;
+        let overlay_dir = match project
+            .find_block(name)
+            .and_then(|b| b.get_block(symbol))
+            .and_then(|g| g.find_text("overlay_dir"))
+        {
+            Some(overlay_dir) => overlay_dir,
+            None => return project,
+        };
+
+        let block_names = project
+            .iter_block()
+            .map(|(block_name, _)| block_name.to_string())
+            .collect::<Vec<_>>();
+
+        let mut project = project;
+        for block_name in block_names {
+            let block = match project.find_block(&block_name) {
+                Some(block) => block,
+                None => continue,
+            };
+
+            for symbol in ["install", "make_mime", "lab"] {
+                if block
+                    .get_block(symbol)
+                    .map(|g| g.find_text("overlay_dir").is_none())
+                    .unwrap_or_default()
+                {
+                    project = project.with_block(&block_name, symbol, |c| {
+                        c.add_text_attr("overlay_dir", &overlay_dir);
+                    });
+                }
+            }
+        }
+
+        project
+    }
+
+    /// Sets the overlay directory, files in the overlay shadow embedded files w/ the same path
+    pub fn overlay_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.overlay_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the overlay directory for a prefix, files in the overlay take precedence over embedded files
+    pub fn overlay(mut self, prefix: impl AsRef<str>, dir: impl Into<PathBuf>) -> Self {
+        self.overlays.insert(
+            prefix.as_ref().trim_end_matches('/').to_string(),
+            dir.into(),
+        );
+        self
+    }
+
+    /// Resolves the content of a path, returns None if the path could not be found
+    pub fn get(&self, path: impl AsRef<str>) -> Option<(Vec<u8>, Origin)> {
+        let path = path.as_ref().trim_start_matches("./");
+
+        if let Some(overlay) =
+            Self::split(path).and_then(|(prefix, rest)| self.overlay_path(prefix, rest))
+        {
+            if let Ok(content) = std::fs::read(overlay) {
+                return Some((content, Origin::Overlay));
+            }
+        }
+
+        let path_buf = PathBuf::from(path);
+        if path_buf.is_file() {
+            if let Ok(content) = std::fs::read(&path_buf) {
+                return Some((content, Origin::Filesystem));
+            }
+        }
+
+        Self::split(path)
+            .and_then(|(prefix, rest)| Self::embedded(prefix, rest))
+            .map(|content| (content.to_vec(), Origin::Embedded))
+    }
+
+    /// Resolves the content of a path from an overlay or the embedded folders, w/o reading the path from disk
+    ///
+    /// Returns None if the path has a `..`, root or prefix component, so that paths from a request can't escape the overlays
+    pub fn get_virtual(&self, path: impl AsRef<str>) -> Option<(Vec<u8>, Origin)> {
+        let path = path.as_ref().trim_start_matches("./");
+
+        if !std::path::Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
+        }
+
+        let (prefix, rest) = Self::split(path)?;
+        if let Some(overlay) = self.overlay_path(prefix, rest) {
+            if let Ok(content) = std::fs::read(overlay) {
+                return Some((content, Origin::Overlay));
+            }
+        }
+
+        Self::embedded(prefix, rest).map(|content| (content.to_vec(), Origin::Embedded))
+    }
+
+    /// Resolves the content of a path as a string
+    pub fn get_string(&self, path: impl AsRef<str>) -> Option<String> {
+        self.get(path)
+            .and_then(|(content, _)| String::from_utf8(content).ok())
+    }
+
+    /// Returns true if the path can be resolved
+    pub fn exists(&self, path: impl AsRef<str>) -> bool {
+        self.origin(path).is_some()
+    }
+
+    /// Returns where a path would be resolved from, w/o reading the content
+    pub fn origin(&self, path: impl AsRef<str>) -> Option<Origin> {
+        let path = path.as_ref().trim_start_matches("./");
+        let split = Self::split(path);
+
+        match split {
+            Some((prefix, rest)) if self.overlay_path(prefix, rest).is_some() => {
+                Some(Origin::Overlay)
+            }
+            _ if PathBuf::from(path).is_file() => Some(Origin::Filesystem),
+            Some((prefix, rest)) if Self::embedded(prefix, rest).is_some() => {
+                Some(Origin::Embedded)
+            }
+            _ => None,
+        }
+    }
+
+    /// Lists every path under a prefix, or every prefix if None, w/ the origin each path resolves from
+    pub fn list(&self, prefix: Option<&str>) -> Vec<(String, Origin)> {
+        let mut paths = BTreeMap::new();
+
+        for p in PREFIXES.iter().filter(|p| {
+            prefix
+                .map(|f| f.trim_end_matches('/') == **p)
+                .unwrap_or(true)
+        }) {
+            for file in Self::embedded_files(p) {
+                paths.insert(format!("{p}/{file}"), Origin::Embedded);
+            }
+
+            for overlay in self.overlay_dirs(p) {
+                for entry in walkdir::WalkDir::new(&overlay)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                {
+                    if let Some(rest) = entry
+                        .path()
+                        .strip_prefix(&overlay)
+                        .ok()
+                        .and_then(|r| r.to_str())
+                    {
+                        let rest = rest.replace(std::path::MAIN_SEPARATOR, "/");
+                        paths.insert(format!("{p}/{rest}"), Origin::Overlay);
+                    }
+                }
+            }
+        }
+
+        paths
+            .into_iter()
+            .map(|(path, origin)| {
+                let origin = self.origin(&path).unwrap_or(origin);
+                (path, origin)
+            })
+            .collect()
+    }
+
+    /// Returns a unified diff of every embedded file that is shadowed by an overlay, under a prefix or every prefix if None
+    ///
+    /// Overlay files that don't shadow an embedded file are listed as added
+    pub fn diff(&self, prefix: Option<&str>) -> String {
+        let mut diff = String::new();
+
+        for (path, origin) in self.list(prefix) {
+            if origin != Origin::Overlay {
+                continue;
+            }
+
+            let overlay = self.get(&path).map(|(c, _)| c).unwrap_or_default();
+            let embedded =
+                Self::split(&path).and_then(|(prefix, rest)| Self::embedded(prefix, rest));
+
+            match embedded {
+                Some(embedded) if embedded.as_ref() == overlay.as_slice() => {
+                    diff.push_str(&format!("unchanged {path}\n"));
+                }
+                Some(embedded) => {
+                    match (
+                        std::str::from_utf8(embedded.as_ref()),
+                        std::str::from_utf8(&overlay),
+                    ) {
+                        (Ok(embedded), Ok(overlay)) => {
+                            diff.push_str(&format!("modified  {path}\n"));
+                            diff.push_str(
+                                &TextDiff::from_lines(embedded, overlay)
+                                    .unified_diff()
+                                    .header(&format!("embedded/{path}"), &format!("overlay/{path}"))
+                                    .to_string(),
+                            );
+                        }
+                        _ => diff.push_str(&format!("modified  {path} (binary)\n")),
+                    }
+                }
+                None => diff.push_str(&format!("added     {path}\n")),
+            }
+        }
+
+        diff
+    }
+
+    /// Returns the embedded content for a path under a prefix
+    pub fn embedded(prefix: &str, rest: &str) -> Option<Cow<'static, [u8]>> {
+        match prefix {
+            "lib/sh" => Shell::get(rest).map(|f| f.data),
+            "lib/cloud_init" => UserData::get(rest).map(|f| f.data),
+            "lib/elm" => Elm::get(rest).map(|f| f.data),
+            "design" => Design::get(rest).map(|f| f.data),
+            _ => None,
+        }
+    }
+
+    /// Returns the embedded files under a prefix, relative to the prefix
+    fn embedded_files(prefix: &str) -> Vec<String> {
+        let files: Vec<Cow<'static, str>> = match prefix {
+            "lib/sh" => Shell::iter().collect(),
+            "lib/cloud_init" => UserData::iter().collect(),
+            "lib/elm" => Elm::iter().collect(),
+            "design" => Design::iter().collect(),
+            _ => vec![],
+        };
+
+        files.into_iter().map(|f| f.to_string()).collect()
+    }
+
+    /// Returns the path of the overlay file that shadows a path under a prefix, if one exists
+    fn overlay_path(&self, prefix: &str, rest: &str) -> Option<PathBuf> {
+        self.overlay_dirs(prefix)
+            .into_iter()
+            .map(|dir| dir.join(rest))
+            .find(|path| path.is_file())
+    }
+
+    /// Returns the overlay directories for a prefix, in order of precedence
+    fn overlay_dirs(&self, prefix: &str) -> Vec<PathBuf> {
+        self.overlays
+            .get(prefix)
+            .cloned()
+            .into_iter()
+            .chain(self.overlay_dir.as_ref().map(|dir| dir.join(prefix)))
+            .collect()
+    }
+
+    /// Splits a path into a known prefix, and the rest of the path
+    fn split(path: &str) -> Option<(&'static str, &str)> {
+        PREFIXES.iter().find_map(|prefix| {
+            path.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .map(|rest| (*prefix, rest))
+        })
+    }
+}
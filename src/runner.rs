@@ -0,0 +1,326 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use lifec::{
+    plugins::{Plugin, Project, ThunkContext},
+    AttributeGraph, Value,
+};
+use tracing::{event, Level};
+
+use crate::{
+    assert::Assert,
+    configs, plugins,
+    resources::{Resolver, PREFIXES},
+};
+
+/// Executes the engines of a project sequentially w/o the editor world
+///
+/// Each event of an engine is called in the order it was defined, and the result of the
+/// previous event is passed to the next one. Plugins that host servers are not supported,
+/// since they do not return until they are cancelled.
+///
+pub struct Runner {
+    project: Project,
+    /// The .runmd the project was loaded from, events are ordered by where they are defined in it
+    source: String,
+    tokio_runtime: tokio::runtime::Runtime,
+    /// Result of the last event for each block
+    outputs: BTreeMap<String, AttributeGraph>,
+    /// If set, relative paths in each event's attributes are resolved against this directory, see `with_work_dir`
+    work_dir: Option<PathBuf>,
+}
+
+/// An event defined in an engine's `call` block,
+///
+/// ex. `define a_install install .symbol acr_login`
+#[derive(Debug)]
+struct Event {
+    /// Name of the event, ex. `a_install`
+    name: String,
+    /// Plugin symbol, ex. `install`
+    plugin: String,
+    /// Block the plugin reads its attributes from, ex. `acr_login`
+    block_name: String,
+    /// Optional config to apply before calling the plugin, ex. `cloud_init`
+    config: Option<String>,
+}
+
+impl Runner {
+    /// Creates a new runner for the project, loaded from the .runmd in `source`
+    pub fn new(project: Project, source: impl Into<String>) -> Self {
+        Self {
+            project: Resolver::apply(project),
+            source: source.into(),
+            tokio_runtime: tokio::runtime::Runtime::new()
+                .expect("should be able to create a tokio runtime"),
+            outputs: BTreeMap::default(),
+            work_dir: None,
+        }
+    }
+
+    /// Resolves relative `work_dir`, `file_dst`, `cache_dir` and `current_dir` attributes against `work_dir`, blocks w/o a `work_dir` or `current_dir` use `work_dir`
+    ///
+    /// Paths under an embedded prefix, ex. `lib/cloud_init`, are left as is, since those are read w/ the resolver
+    pub fn with_work_dir(mut self, work_dir: impl Into<PathBuf>) -> Self {
+        self.work_dir = Some(work_dir.into());
+        self
+    }
+
+    /// Reads the .runmd of a project from a file, an embedded `design/` path, or a lab in `lab_dir`
+    pub fn load(project_src: impl AsRef<str>, lab_dir: Option<String>) -> Option<String> {
+        let project_src = project_src.as_ref();
+
+        if let Some(source) = Resolver::new().get_string(project_src) {
+            return Some(source);
+        }
+
+        if let Some(lab_dir) = lab_dir {
+            let path = PathBuf::from(lab_dir).join(project_src).join(".runmd");
+            event!(Level::DEBUG, "trying to find lab at {:?}", path);
+            if let Ok(source) = std::fs::read_to_string(path) {
+                return Some(source);
+            }
+        }
+
+        None
+    }
+
+    /// Runs each engine in order, returns false if any event failed
+    pub fn run(&mut self, engines: Vec<String>) -> bool {
+        for engine in engines {
+            let events = self.events(&engine);
+            if events.is_empty() {
+                eprintln!("[{engine}] no events found, engines must have a `call` block");
+                return false;
+            }
+
+            eprintln!("[{engine}] starting, {} events", events.len());
+            let started = Instant::now();
+            let mut previous: Option<ThunkContext> = None;
+
+            for event in events {
+                let Event {
+                    name,
+                    plugin,
+                    block_name,
+                    ..
+                } = &event;
+
+                eprintln!("[{engine}] {name} - {plugin} {block_name}");
+                let event_started = Instant::now();
+                match self.call(&event, previous.take()) {
+                    Ok(result) => {
+                        eprintln!(
+                            "[{engine}] {name} - ok ({:.2}s)",
+                            event_started.elapsed().as_secs_f32()
+                        );
+                        self.outputs
+                            .insert(block_name.to_string(), result.as_ref().clone());
+                        previous = Some(result);
+                    }
+                    Err(err) => {
+                        eprintln!("[{engine}] {name} - failed, {err}");
+                        return false;
+                    }
+                }
+            }
+
+            eprintln!(
+                "[{engine}] completed ({:.2}s)",
+                started.elapsed().as_secs_f32()
+            );
+        }
+
+        true
+    }
+
+    /// Returns the project being run
+    pub fn project(&self) -> &Project {
+        &self.project
+    }
+
+    /// Returns the result of the last event for each block that has been run
+    pub fn outputs(&self) -> &BTreeMap<String, AttributeGraph> {
+        &self.outputs
+    }
+
+    /// Returns the tokio runtime used to run plugins
+    pub fn tokio_runtime(&self) -> &tokio::runtime::Runtime {
+        &self.tokio_runtime
+    }
+
+    /// Returns true if any event in the engine calls a plugin that hosts a server, which the runner does not support
+    pub fn hosts_server(&self, engine: impl AsRef<str>) -> bool {
+        let servers = plugins()
+            .into_iter()
+            .filter(|p| p.hosts_server)
+            .map(|p| p.symbol)
+            .collect::<Vec<_>>();
+
+        self.events(engine)
+            .iter()
+            .any(|e| servers.contains(&e.plugin.as_str()))
+    }
+
+    /// Returns the events defined in the engine's `call` block, in the order they are defined
+    fn events(&self, engine: impl AsRef<str>) -> Vec<Event> {
+        let mut events = vec![];
+
+        if let Some(call) = self
+            .project
+            .find_block(engine.as_ref())
+            .and_then(|b| b.get_block("call"))
+        {
+            for plugin in plugins() {
+                for (name, value) in call.clone().find_symbol_values(plugin.symbol) {
+                    let suffix = format!("::{}", plugin.symbol);
+                    if let Some(name) = name.strip_suffix(&suffix) {
+                        let (block_name, config) = match value {
+                            Value::Symbol(block_name) => (block_name, None),
+                            Value::TextBuffer(config) => (name.to_string(), Some(config)),
+                            _ => continue,
+                        };
+
+                        events.push(Event {
+                            name: name.to_string(),
+                            plugin: plugin.symbol.to_string(),
+                            block_name,
+                            config,
+                        });
+                    }
+                }
+            }
+        }
+
+        // The graph does not keep the order attributes were added in, so the order is read from the source
+        let order = Runner::definition_order(&self.source, engine.as_ref());
+        events.sort_by_key(|e| {
+            (
+                order.iter().position(|name| *name == e.name).unwrap_or(usize::MAX),
+                e.name.to_string(),
+            )
+        });
+        events
+    }
+
+    /// Returns the name of each event defined in the engine's `call` blocks, in the order they appear in `source`
+    fn definition_order(source: &str, engine: &str) -> Vec<String> {
+        let mut order = vec![];
+        let mut in_call = false;
+
+        for line in source.lines().map(str::trim) {
+            if let Some(header) = line.strip_prefix("```") {
+                in_call = header.split_whitespace().eq([engine, "call"]);
+            } else if in_call {
+                let mut tokens = line.split_whitespace();
+                if let (Some("define"), Some(name)) = (tokens.next(), tokens.next()) {
+                    order.push(name.to_string());
+                }
+            }
+        }
+
+        order
+    }
+
+    /// Calls the plugin for an event and waits for the result
+    fn call(&self, event: &Event, previous: Option<ThunkContext>) -> Result<ThunkContext, String> {
+        let Event {
+            plugin,
+            block_name,
+            config,
+            ..
+        } = event;
+
+        let call_fn = plugins()
+            .into_iter()
+            .find(|p| p.symbol == plugin.as_str() && !p.hosts_server)
+            .map(|p| p.call)
+            .ok_or(format!("{plugin} is not supported by the headless runner"))?;
+
+        let graph = self
+            .project
+            .find_block(block_name)
+            .and_then(|b| b.get_block(plugin))
+            .unwrap_or(AttributeGraph::from(0));
+
+        let mut tc = ThunkContext::default();
+        tc.block.block_name = block_name.to_string();
+        tc.handle = Some(self.tokio_runtime.handle().clone());
+        *tc.as_mut() = graph;
+
+        if let Some(config) = config {
+            match configs().into_iter().find(|c| c.0 == config.as_str()) {
+                Some(config) => (config.1)(&mut tc),
+                None => return Err(format!("config {config} was not found")),
+            }
+        }
+
+        if let Some(work_dir) = self.work_dir.as_ref() {
+            Runner::rebase(tc.as_mut(), work_dir);
+        }
+
+        // Pass along the output of the previous event, w/o overwriting this block's attributes
+        if let Some(previous) = previous {
+            for attribute in previous.as_ref().iter_attributes() {
+                let name = attribute.name();
+                match attribute.value() {
+                    Value::BinaryVector(content) if tc.as_ref().find_binary(name).is_none() => {
+                        tc.as_mut().add_binary_attr(name, content.to_vec());
+                    }
+                    Value::TextBuffer(text) if tc.as_ref().find_text(name).is_none() => {
+                        tc.as_mut().add_text_attr(name, text);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if plugin == Assert::symbol() {
+            Assert::with_sources(tc.as_mut(), &self.outputs);
+        }
+
+        match call_fn(&mut tc) {
+            Some((task, _cancel)) => match self.tokio_runtime.block_on(task) {
+                Ok(Some(result)) => match result.get_errors() {
+                    Some(error_context) => {
+                        let mut errors = vec![];
+                        for (name, error) in error_context.errors() {
+                            errors.push(format!("{name}: {error}"));
+                        }
+                        Err(errors.join(", "))
+                    }
+                    None if result.as_ref().is_enabled("assert_failed").unwrap_or_default() => {
+                        Err("one or more assertions failed".to_string())
+                    }
+                    None => Ok(result),
+                },
+                Ok(None) => Err(format!("{plugin} did not return a result")),
+                Err(err) => Err(format!("{plugin} task failed, {err}")),
+            },
+            None => Err(format!("{plugin} did not start")),
+        }
+    }
+
+    /// Resolves the relative paths of `graph` against `work_dir`, see `with_work_dir`
+    fn rebase(graph: &mut AttributeGraph, work_dir: &Path) {
+        if graph.find_text("work_dir").is_none() {
+            graph.add_text_attr("work_dir", work_dir.to_string_lossy());
+        }
+
+        // Processes run in their `current_dir`, so commands w/o one run in the work_dir instead of the current directory
+        if graph.find_text("current_dir").is_none() {
+            graph.add_text_attr("current_dir", work_dir.to_string_lossy());
+        }
+
+        for name in ["work_dir", "file_dst", "cache_dir", "current_dir"] {
+            if let Some(path) = graph.find_text(name) {
+                if PathBuf::from(&path).is_relative() && !PREFIXES.iter().any(|p| path.starts_with(p)) {
+                    graph.add_text_attr(name, work_dir.join(path).to_string_lossy());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::install::Install;
+
+/// Local stand-in for an https file server and an OCI distribution registry, used by tests
+///
+/// Serves plain files, and the subset of the distribution api chiron uses: blobs, monolithic uploads,
+/// manifests, tags and the catalog. If `token_auth` is enabled, every `/v2/` request must have the token
+/// issued by the stand-in's `/token` realm
+///
+#[derive(Clone, Default)]
+pub struct StandIn {
+    /// Host and port the stand-in is listening on, ex. `127.0.0.1:41234`
+    pub host: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<String, Vec<u8>>,
+    blobs: BTreeMap<(String, String), Vec<u8>>,
+    manifests: BTreeMap<(String, String), Vec<u8>>,
+    uploads: usize,
+    token_auth: bool,
+    /// Realm host to issue challenges w/, defaults to the stand-in's own host
+    realm_host: Option<String>,
+    /// Method and path of every request received
+    requests: Vec<String>,
+    /// Authorization header of every request received, by path
+    authorizations: Vec<(String, Option<String>)>,
+}
+
+/// Token issued by the `/token` realm
+pub const TOKEN: &str = "stand-in-token";
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    fn body(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![],
+            body,
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+impl StandIn {
+    /// Starts the stand-in on a free local port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("should be able to bind a local port");
+        let stand_in = Self {
+            host: listener
+                .local_addr()
+                .expect("should have a local address")
+                .to_string(),
+            ..Default::default()
+        };
+
+        let server = stand_in.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move { server.serve(stream).await });
+            }
+        });
+
+        stand_in
+    }
+
+    /// Returns the url of a path on the stand-in, ex. `http://127.0.0.1:41234/scripts/fix.sh`
+    pub fn url(&self, path: impl AsRef<str>) -> String {
+        format!("http://{}/{}", self.host, path.as_ref().trim_start_matches('/'))
+    }
+
+    /// Serves `content` at `path`
+    pub fn add_file(&self, path: impl AsRef<str>, content: impl Into<Vec<u8>>) {
+        self.lock()
+            .files
+            .insert(format!("/{}", path.as_ref().trim_start_matches('/')), content.into());
+    }
+
+    /// Pushes a single blob artifact to `repository:tag`, returns the digest of the manifest
+    pub fn add_artifact(&self, repository: &str, tag: &str, title: &str, content: &[u8]) -> String {
+        let digest = format!("sha256:{}", Install::sha256(content));
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": format!("sha256:{}", Install::sha256(b"{}")),
+                "size": 2,
+            },
+            "layers": [{
+                "mediaType": "application/octet-stream",
+                "digest": digest,
+                "size": content.len(),
+                "annotations": { "org.opencontainers.image.title": title },
+            }],
+        });
+        let manifest = serde_json::to_vec(&manifest).expect("should serialize");
+        let manifest_digest = format!("sha256:{}", Install::sha256(&manifest));
+
+        let mut state = self.lock();
+        state.blobs.insert((repository.to_string(), digest), content.to_vec());
+        state
+            .manifests
+            .insert((repository.to_string(), tag.to_string()), manifest.clone());
+        state
+            .manifests
+            .insert((repository.to_string(), manifest_digest.clone()), manifest);
+        manifest_digest
+    }
+
+    /// Requires the token issued by the `/token` realm for every `/v2/` request
+    pub fn enable_token_auth(&self) {
+        self.lock().token_auth = true;
+    }
+
+    /// Issues challenges w/ a realm on a different host, ex. to check credentials are not sent to it
+    pub fn set_realm_host(&self, host: impl Into<String>) {
+        self.lock().realm_host = Some(host.into());
+    }
+
+    /// Returns the method and path of every request received, ex. `GET /v2/_catalog`
+    pub fn requests(&self) -> Vec<String> {
+        self.lock().requests.clone()
+    }
+
+    /// Returns the authorization header of every request received for `path`
+    pub fn authorizations(&self, path: impl AsRef<str>) -> Vec<Option<String>> {
+        self.lock()
+            .authorizations
+            .iter()
+            .filter(|(p, _)| p == path.as_ref())
+            .map(|(_, a)| a.clone())
+            .collect()
+    }
+
+    /// Returns true if the repository has a manifest for the reference
+    pub fn has_manifest(&self, repository: &str, reference: &str) -> bool {
+        self.lock()
+            .manifests
+            .contains_key(&(repository.to_string(), reference.to_string()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("should be able to lock the stand-in state")
+    }
+
+    async fn serve(&self, mut stream: TcpStream) {
+        if let Some(request) = Self::read(&mut stream).await {
+            let reply = self.handle(request);
+
+            let mut response = format!(
+                "HTTP/1.1 {} Stand-In\r\ncontent-length: {}\r\nconnection: close\r\n",
+                reply.status,
+                reply.body.len()
+            );
+            for (name, value) in reply.headers {
+                response.push_str(&format!("{name}: {value}\r\n"));
+            }
+            response.push_str("\r\n");
+
+            let mut response = response.into_bytes();
+            response.extend(reply.body);
+            stream.write_all(&response).await.ok();
+            stream.shutdown().await.ok();
+        }
+    }
+
+    async fn read(stream: &mut TcpStream) -> Option<Request> {
+        let mut buffer = vec![];
+        let mut chunk = [0; 4096];
+
+        let header_end = loop {
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+            if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let target = request_line.next()?.to_string();
+
+        let mut content_length = 0;
+        let mut authorization = None;
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                match name.trim().to_lowercase().as_str() {
+                    "content-length" => content_length = value.trim().parse().unwrap_or_default(),
+                    "authorization" => authorization = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut body = buffer[header_end..].to_vec();
+        while body.len() < content_length {
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (target, String::new()),
+        };
+
+        Some(Request {
+            method,
+            path,
+            query,
+            authorization,
+            body,
+        })
+    }
+
+    fn handle(&self, request: Request) -> Reply {
+        let Request {
+            method,
+            path,
+            query,
+            authorization,
+            body,
+        } = request;
+
+        let mut state = self.lock();
+        state.requests.push(format!("{method} {path}"));
+        state.authorizations.push((path.to_string(), authorization.clone()));
+
+        if path == "/token" {
+            return Reply::body(serde_json::to_vec(&json!({ "token": TOKEN })).unwrap_or_default());
+        }
+
+        let v2 = match path.strip_prefix("/v2/") {
+            Some(v2) => v2,
+            None => {
+                return match state.files.get(&path) {
+                    Some(content) if method == "GET" => Reply::body(content.clone()),
+                    _ => Reply::status(404),
+                };
+            }
+        };
+
+        if state.token_auth && authorization.as_deref() != Some(format!("Bearer {TOKEN}").as_str()) {
+            let realm_host = state.realm_host.clone().unwrap_or_else(|| self.host.to_string());
+            return Reply::status(401).header(
+                "www-authenticate",
+                format!("Bearer realm=\"http://{realm_host}/token\",service=\"stand-in\""),
+            );
+        }
+
+        let found = |content: Option<&Vec<u8>>| match (content, method.as_str()) {
+            (Some(_), "HEAD") => Reply::status(200),
+            (Some(content), _) => Reply::body(content.clone()),
+            (None, _) => Reply::status(404),
+        };
+
+        if v2 == "_catalog" {
+            let mut repositories = state
+                .manifests
+                .keys()
+                .map(|(repository, _)| repository.to_string())
+                .collect::<Vec<_>>();
+            repositories.dedup();
+            return Reply::body(serde_json::to_vec(&json!({ "repositories": repositories })).unwrap_or_default());
+        }
+
+        if let Some(repository) = v2.strip_suffix("/tags/list") {
+            let tags = state
+                .manifests
+                .keys()
+                .filter(|(r, reference)| r == repository && !reference.starts_with("sha256:"))
+                .map(|(_, tag)| tag.to_string())
+                .collect::<Vec<_>>();
+            return Reply::body(
+                serde_json::to_vec(&json!({ "name": repository, "tags": tags })).unwrap_or_default(),
+            );
+        }
+
+        if let Some((repository, reference)) = v2.split_once("/manifests/") {
+            let key = (repository.to_string(), reference.to_string());
+            return match method.as_str() {
+                "PUT" => {
+                    let digest = format!("sha256:{}", Install::sha256(&body));
+                    state.manifests.insert(key, body.clone());
+                    state.manifests.insert((repository.to_string(), digest.to_string()), body);
+                    Reply::status(201).header("docker-content-digest", digest)
+                }
+                _ => found(state.manifests.get(&key)),
+            };
+        }
+
+        if let Some((repository, upload)) = v2.split_once("/blobs/uploads/") {
+            return match method.as_str() {
+                "POST" => {
+                    state.uploads += 1;
+                    Reply::status(202).header(
+                        "location",
+                        format!("/v2/{repository}/blobs/uploads/{}", state.uploads),
+                    )
+                }
+                "PUT" if !upload.is_empty() => {
+                    let digest = query
+                        .split('&')
+                        .find_map(|p| p.strip_prefix("digest="))
+                        .unwrap_or_default()
+                        .replace("%3A", ":");
+                    if digest != format!("sha256:{}", Install::sha256(&body)) {
+                        return Reply::status(400);
+                    }
+                    state.blobs.insert((repository.to_string(), digest), body);
+                    Reply::status(201)
+                }
+                _ => Reply::status(405),
+            };
+        }
+
+        if let Some((repository, digest)) = v2.split_once("/blobs/") {
+            return found(state.blobs.get(&(repository.to_string(), digest.to_string())));
+        }
+
+        Reply::status(404)
+    }
+}
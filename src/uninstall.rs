@@ -0,0 +1,52 @@
+use lifec::{
+    plugins::{Plugin, ThunkContext},
+    Component, DenseVecStorage,
+};
+use tracing::{event, Level};
+
+use crate::ledger::Ledger;
+
+/// Removes the files installed by a block, restoring any file that was backed up
+///
+/// Reads the install ledger in `{work_dir}`, files modified since they were installed are only removed if `force` is enabled.
+/// If `dry_run` is enabled, the files that would be removed or restored are printed, w/o changing anything
+#[derive(Component, Default)]
+#[storage(DenseVecStorage)]
+pub struct Uninstall;
+
+impl Plugin<ThunkContext> for Uninstall {
+    fn symbol() -> &'static str {
+        "uninstall"
+    }
+
+    fn description() -> &'static str {
+        "Removes the files installed by {block_name} in {work_dir}"
+    }
+
+    fn call_with_context(context: &mut ThunkContext) -> Option<lifec::plugins::AsyncContext> {
+        context.clone().task(|_| {
+            let tc = context.clone();
+            async move {
+                let block_name = tc.block.block_name.to_string();
+                let work_dir = tc.as_ref().find_text("work_dir").unwrap_or_default();
+                let force = tc.as_ref().is_enabled("force").unwrap_or_default();
+                let dry_run = tc.as_ref().is_enabled("dry_run").unwrap_or_default();
+
+                match Ledger::uninstall(&work_dir, &block_name, force, dry_run).await {
+                    Ok(log) => {
+                        for line in log {
+                            tc.update_status_only(&line).await;
+                            eprintln!("{line}");
+                        }
+                        Some(tc)
+                    }
+                    Err(err) => {
+                        eprintln!("uninstall: {err}");
+                        event!(Level::ERROR, "could not uninstall {block_name}, {err}");
+                        None
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lifec::plugins::Project;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{event, Level};
+
+use crate::install::Install;
+
+/// A `.runmd` that changed on disk, and parsed successfully
+///
+#[derive(Debug, Clone)]
+pub struct Reload {
+    /// Path of the file that changed
+    pub path: PathBuf,
+    /// The new content of the file
+    pub content: String,
+}
+
+/// Watches `.runmd` files for changes, re-parsing each changed file before it's reported
+///
+/// Files that fail to parse, or whose content did not change, are not reported. The watch stops when this is dropped
+pub struct HotReload {
+    _watcher: RecommendedWatcher,
+}
+
+impl HotReload {
+    /// Watches a `.runmd` file, or every `.runmd` file under a directory, calling `on_reload` for each file that changed
+    pub fn watch(
+        path: impl AsRef<Path>,
+        on_reload: impl Fn(Reload) + Send + 'static,
+    ) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+
+        // Files are watched through their parent directory, since editors often save by replacing the file
+        let (watch_dir, mode, only) = if path.is_file() {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            (parent, RecursiveMode::NonRecursive, path.file_name().map(|f| f.to_os_string()))
+        } else if path.is_dir() {
+            (path.clone(), RecursiveMode::Recursive, None)
+        } else {
+            return Err(format!("{:?} does not exist", path));
+        };
+
+        let last = Mutex::new(HashMap::<PathBuf, String>::new());
+        let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            let event = match result {
+                Ok(event) => event,
+                Err(err) => {
+                    event!(Level::ERROR, "error watching files, {err}");
+                    return;
+                }
+            };
+
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+
+            for changed in event.paths {
+                let is_match = match &only {
+                    Some(file_name) => changed.file_name() == Some(file_name.as_os_str()),
+                    None => changed.file_name().and_then(|f| f.to_str()) == Some(".runmd"),
+                };
+
+                if !is_match {
+                    continue;
+                }
+
+                let content = match std::fs::read_to_string(&changed) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        event!(Level::DEBUG, "could not read {:?}, {err}", changed);
+                        continue;
+                    }
+                };
+
+                let digest = Install::sha256(&content);
+                if let Ok(mut last) = last.lock() {
+                    if last.get(&changed) == Some(&digest) {
+                        continue;
+                    }
+                    last.insert(changed.clone(), digest);
+                }
+
+                if Project::load_content(content.clone()).is_none() {
+                    event!(Level::ERROR, "could not parse {:?}, skipping reload", changed);
+                    eprintln!("could not parse {:?}, skipping reload", changed);
+                    continue;
+                }
+
+                event!(Level::DEBUG, "reloading {:?}", changed);
+                on_reload(Reload {
+                    path: changed,
+                    content,
+                });
+            }
+        })
+        .map_err(|e| format!("could not create watcher, {e}"))?;
+
+        watcher
+            .watch(&watch_dir, mode)
+            .map_err(|e| format!("could not watch {:?}, {e}", watch_dir))?;
+
+        Ok(Self { _watcher: watcher })
+    }
+}